@@ -1,27 +1,122 @@
-use std::{borrow::Cow, error, fmt};
+use std::{borrow::Cow, error, ffi::NulError, fmt, io};
+
+/// What went wrong in a `TclError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TclErrorKind {
+    /// The interpreter was used after it was deleted.
+    InterpDeleted,
+
+    /// A string that had to be passed to Tcl as a C string contained a NUL byte.
+    NulByte,
+
+    /// A Tcl object could not be converted to the requested Rust type.
+    Conversion,
+
+    /// Evaluating Tcl code failed. The error carries Tcl's return options.
+    Script,
+
+    /// Anything else, including errors created by Rust code.
+    Other,
+}
 
 /// Represents an error returned from the Tcl interpreter.
 ///
-/// This is usually just the error returned from Tcl itself.
+/// This is usually just the error returned from Tcl itself, in which case it also carries the
+/// stack trace (`errorInfo`), the machine-readable error code (`errorCode`) and the line the error
+/// happened on.
 #[derive(Debug, Clone)]
-pub struct TclError(pub Cow<'static, str>);
+pub struct TclError {
+    kind: TclErrorKind,
+    message: Cow<'static, str>,
+    error_info: Option<String>,
+    error_code: Option<String>,
+    line: Option<u32>,
+}
 
 impl TclError {
     pub fn new(s: impl Into<Cow<'static, str>>) -> Self {
-        Self(s.into())
+        Self::with_kind(TclErrorKind::Other, s)
+    }
+
+    pub fn with_kind(kind: TclErrorKind, s: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            kind,
+            message: s.into(),
+            error_info: None,
+            error_code: None,
+            line: None,
+        }
+    }
+
+    /// Create an error for a failed script from the interpreter's return options.
+    pub(crate) fn script(
+        message: String,
+        error_info: Option<String>,
+        error_code: Option<String>,
+        line: Option<u32>,
+    ) -> Self {
+        Self {
+            kind: TclErrorKind::Script,
+            message: message.into(),
+            error_info,
+            error_code,
+            line,
+        }
+    }
+
+    pub fn kind(&self) -> TclErrorKind {
+        self.kind
+    }
+
+    /// The error message, which is what a Tcl script would see as the result.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The Tcl stack trace, as stored in `errorInfo`.
+    pub fn error_info(&self) -> Option<&str> {
+        self.error_info.as_ref().map(|s| s as &str)
+    }
+
+    /// The Tcl error code, as stored in `errorCode`. This is a list, e.g. `TCL LOOKUP COMMAND foo`.
+    pub fn error_code(&self) -> Option<&str> {
+        self.error_code.as_ref().map(|s| s as &str)
+    }
+
+    /// The line of the script the error happened on.
+    pub fn line(&self) -> Option<u32> {
+        self.line
     }
 }
 
 impl fmt::Display for TclError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.message)
     }
 }
 
 impl error::Error for TclError {}
 
-impl From<TclError> for std::io::Error {
-    fn from(err: TclError) -> std::io::Error {
-        std::io::Error::new(std::io::ErrorKind::Other, err)
+impl From<NulError> for TclError {
+    fn from(err: NulError) -> TclError {
+        TclError::with_kind(TclErrorKind::NulByte, err.to_string())
+    }
+}
+
+impl From<io::Error> for TclError {
+    fn from(err: io::Error) -> TclError {
+        TclError::new(err.to_string())
+    }
+}
+
+impl From<TclError> for io::Error {
+    fn from(err: TclError) -> io::Error {
+        let kind = match err.kind {
+            TclErrorKind::NulByte => io::ErrorKind::InvalidInput,
+            TclErrorKind::Conversion => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        };
+
+        io::Error::new(kind, err)
     }
 }
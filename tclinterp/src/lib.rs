@@ -1,17 +1,55 @@
 #![deny(unused_imports, unused_must_use)]
 #![allow(clippy::identity_conversion)] // Because clippy complains about pyo3.
 
-// FIXME: Use a custom-built type instead of `CStr` to handle strings containing NUL bytes.
-
 mod exceptions;
+mod mutf8;
 mod tclinterp;
 mod tclobj;
 mod tclsocket;
 mod wrappers;
 
-pub use crate::exceptions::TclError;
-pub use crate::tclinterp::TclInterp;
-pub use crate::tclobj::{TclObj, ToTclObj};
+use std::{ptr, sync::Once};
+
+pub use crate::exceptions::{TclError, TclErrorKind};
+#[cfg(unix)]
+pub use crate::tclinterp::FileMask;
+pub use crate::tclinterp::{
+    Buffering, CallbackWriter, Completion, EventFlags, IntoCompletion, StdRedirect, TclChannel,
+    TclInterp, TclInterpHandle, TimerToken, TraceCallback, TraceOps, Translation, VarFlags,
+    VarTrace,
+};
+pub use crate::tclobj::{
+    FromTclObj, TclByteArray, TclDict, TclDictIter, TclList, TclListIter, TclObj, ToTclObj,
+};
+pub use crate::tclsocket::{TclListener, TclSocket};
+
+/// Cleans up Tcl's data for a thread when the thread exits.
+struct ThreadFinalizer;
+
+impl Drop for ThreadFinalizer {
+    fn drop(&mut self) {
+        // Otherwise Tcl keeps the thread's notifier around, and a later thread that happens to get
+        // the same id would miss events sent to it by other threads.
+        crate::tclinterp::fail_pending_jobs();
+        unsafe { tcl_sys::Tcl_FinalizeThread() };
+    }
+}
+
+thread_local! {
+    static THREAD_FINALIZER: ThreadFinalizer = ThreadFinalizer;
+}
+
+/// Initialize the Tcl library.
+///
+/// This has to happen before any Tcl object is created. Creating an interpreter does it too, but
+/// objects can be used without one.
+pub(crate) fn init_tcl() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe { tcl_sys::Tcl_FindExecutable(ptr::null()) });
+
+    // This fails if the thread is already exiting, in which case there's nothing left to do.
+    let _ = THREAD_FINALIZER.try_with(|_| {});
+}
 
 #[cfg(test)]
 mod tests {
@@ -28,6 +66,7 @@ mod tests {
             TclInterp::new()
                 .unwrap()
                 .call(&["format", "%s", "hello, world"])
+                .and_then(Completion::into_result)
                 .unwrap(),
             "hello, world"
         );
@@ -41,7 +80,8 @@ mod tests {
         assert!(interp.deleted());
 
         let err = interp.call(&["format", "%s", "test123"]).unwrap_err();
-        assert_eq!(err.0, "Tried to use interpreter after deletion");
+        assert_eq!(err.message(), "Tried to use interpreter after deletion");
+        assert_eq!(err.kind(), TclErrorKind::InterpDeleted);
     }
 
     #[test]
@@ -50,6 +90,7 @@ mod tests {
             TclInterp::new()
                 .unwrap()
                 .eval("format %s {42}".to_owned())
+                .and_then(Completion::into_result)
                 .unwrap(),
             "42"
         );
@@ -59,13 +100,122 @@ mod tests {
     fn test_splitlist() {
         let mut interp = TclInterp::new().unwrap();
 
-        let l1 = interp.call(&["list", "a", "b", "c and d"]).unwrap();
+        let l1 = interp
+            .call(&["list", "a", "b", "c and d"])
+            .and_then(Completion::into_result)
+            .unwrap();
 
         let mut l1_parts = interp.splitlist(&l1 as &str).unwrap();
         l1_parts.insert(0, "list".to_owned());
 
-        let l2 = interp.call(l1_parts.iter().map(|s| s as &str)).unwrap();
+        let l2 = interp
+            .call(l1_parts.iter().map(|s| s as &str))
+            .and_then(Completion::into_result)
+            .unwrap();
 
         assert_eq!(l1, l2);
     }
+
+    #[test]
+    fn test_eval_obj() {
+        let mut interp = TclInterp::new().unwrap();
+
+        let obj = interp
+            .eval_obj("expr {6 * 7}".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(obj.extract::<i32>().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_call_obj() {
+        let mut interp = TclInterp::new().unwrap();
+
+        let list = interp
+            .call_obj(&["list", "a", "b", "c and d"])
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(
+            list.extract::<Vec<String>>().unwrap(),
+            vec!["a".to_owned(), "b".to_owned(), "c and d".to_owned()]
+        );
+
+        // The resulting object can be passed straight back into Tcl.
+        let len = interp
+            .call_obj(vec!["llength".to_tcl_obj(), list])
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(len.extract::<i32>().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_nul_bytes() {
+        let mut interp = TclInterp::new().unwrap();
+
+        assert_eq!(
+            interp
+                .eval("string length \"a\0b\"".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "3"
+        );
+        assert_eq!(
+            interp
+                .eval("format %s \"a\0b\"".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "a\0b"
+        );
+        assert_eq!(
+            interp
+                .call(&["format", "%s", "\0"])
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "\0"
+        );
+        assert_eq!(
+            interp.splitlist("a\0 b").unwrap(),
+            vec!["a\0".to_owned(), "b".to_owned()]
+        );
+
+        assert!(interp.getboolean("true".to_owned()).unwrap());
+        assert!(interp.getboolean("tr\0ue".to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_error_options() {
+        let mut interp = TclInterp::new().unwrap();
+
+        let err = interp
+            .eval("proc fail {} {\n    error boom {} {MY CODE}\n}\n\nfail".to_owned())
+            .unwrap_err();
+        assert_eq!(err.kind(), TclErrorKind::Script);
+        assert_eq!(err.message(), "boom");
+        assert_eq!(err.error_code(), Some("MY CODE"));
+        assert_eq!(err.line(), Some(5));
+
+        let error_info = err.error_info().unwrap();
+        assert!(error_info.starts_with("boom\n    while executing\n\"error boom"));
+        assert!(error_info.contains("(procedure \"fail\" line 2)"));
+
+        let err = interp.eval("nosuchcommand".to_owned()).unwrap_err();
+        assert_eq!(err.error_code(), Some("TCL LOOKUP COMMAND nosuchcommand"));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let err = "x".to_tcl_obj().extract::<i32>().unwrap_err();
+        assert_eq!(err.kind(), TclErrorKind::Conversion);
+        assert_eq!(err.error_info(), None);
+
+        let err = TclError::from(std::ffi::CString::new("a\0b").unwrap_err());
+        assert_eq!(err.kind(), TclErrorKind::NulByte);
+
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "nul byte found in provided data at position: 1"
+        );
+    }
 }
@@ -0,0 +1,116 @@
+//! Conversions between Rust strings and the "modified UTF-8" Tcl uses for string
+//! representations.
+//!
+//! Tcl strings never contain a NUL byte: the NUL character is encoded as `\xC0\x80` instead.
+//! Characters outside of the Basic Multilingual Plane may also show up as a pair of encoded
+//! surrogates, and Tcl treats any byte that isn't part of a valid sequence as the Latin-1
+//! character with the same value.
+
+use std::{borrow::Cow, char, ffi::CString, str};
+
+const REPLACEMENT: char = '\u{FFFD}';
+
+/// Encode a string (given as its UTF-8 bytes) so that it can be handed to Tcl.
+///
+/// The result never contains a NUL byte, so it is also suitable for APIs that take a C string.
+pub fn encode(bytes: &[u8]) -> Cow<'_, [u8]> {
+    if !bytes.contains(&0) {
+        return Cow::Borrowed(bytes);
+    }
+
+    let mut encoded = Vec::with_capacity(bytes.len() + 1);
+    for &byte in bytes {
+        if byte == 0 {
+            encoded.extend_from_slice(b"\xC0\x80");
+        } else {
+            encoded.push(byte);
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// Encode a string into a C string for Tcl APIs that don't take a length.
+pub fn to_cstring(s: &str) -> CString {
+    CString::new(encode(s.as_bytes()).into_owned()).expect("encode() left a NUL byte")
+}
+
+/// Decode a Tcl string representation into a Rust string.
+///
+/// This never fails: anything that isn't valid is decoded the same way Tcl itself would.
+pub fn decode(mut bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(s) = str::from_utf8(bytes) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut decoded = String::with_capacity(bytes.len());
+
+    loop {
+        let valid_up_to = match str::from_utf8(bytes) {
+            Ok(s) => {
+                decoded.push_str(s);
+                break;
+            }
+
+            Err(e) => e.valid_up_to(),
+        };
+
+        decoded.push_str(unsafe { str::from_utf8_unchecked(&bytes[..valid_up_to]) });
+        bytes = &bytes[valid_up_to..];
+
+        let (c, len) = decode_invalid(bytes);
+        decoded.push(c);
+        bytes = &bytes[len..];
+    }
+
+    Cow::Owned(decoded)
+}
+
+/// Decode the sequence at the start of `bytes`, which `str::from_utf8` has rejected.
+///
+/// Returns the decoded character and how many bytes it took up.
+fn decode_invalid(bytes: &[u8]) -> (char, usize) {
+    let is_surrogate = |b: &[u8], range: (u8, u8)| {
+        b.len() >= 3 && b[0] == 0xED && b[1] >= range.0 && b[1] <= range.1
+    };
+    let surrogate_value =
+        |b: &[u8]| 0xD000 | (u32::from(b[1] & 0x3F) << 6) | u32::from(b[2] & 0x3F);
+
+    if bytes.starts_with(b"\xC0\x80") {
+        ('\0', 2)
+    } else if is_surrogate(bytes, (0xA0, 0xAF)) && is_surrogate(&bytes[3..], (0xB0, 0xBF)) {
+        let high = surrogate_value(bytes);
+        let low = surrogate_value(&bytes[3..]);
+        let c = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        (char::from_u32(c).unwrap_or(REPLACEMENT), 6)
+    } else if is_surrogate(bytes, (0xA0, 0xBF)) {
+        (REPLACEMENT, 3)
+    } else {
+        (char::from(bytes[0]), 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b"hello"), Cow::Borrowed(b"hello" as &[u8]));
+        assert_eq!(&encode(b"a\0b") as &[u8], b"a\xC0\x80b");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"hello"), "hello");
+        assert_eq!(decode(b"a\xC0\x80b"), "a\0b");
+        assert_eq!(decode(b"\xED\xA0\xBD\xED\xB8\x80"), "\u{1F600}");
+        assert_eq!(decode(b"x\xEDy"), "x\u{ED}y");
+        assert_eq!(decode(b"\xFF"), "\u{FF}");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let s = "nul \0 emoji \u{1F600} done";
+        assert_eq!(decode(&encode(s.as_bytes())), s);
+    }
+}
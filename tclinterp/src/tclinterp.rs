@@ -4,17 +4,18 @@ use std::{
     mem,
     os::raw::*,
     ptr::{self, NonNull},
-    rc::Rc,
+    rc::{Rc, Weak},
     slice,
     sync::Mutex,
 };
 
-use log::{debug, trace};
+use log::{debug, error, trace};
 
 use crate::{
-    exceptions::TclError,
-    tclobj::{TclObj, ToTclObj},
-    wrappers::Objv,
+    exceptions::{TclError, TclErrorKind},
+    mutf8,
+    tclobj::{TclDict, TclObj, ToTclObj},
+    wrappers::{catch_panic, catch_panic_with, Objv},
 };
 
 /// Access a TclInterpData attribute through the Rc<Mutex<_>>.
@@ -32,10 +33,42 @@ use preserve::Preserve;
 
 mod channel;
 
+mod tclchannel;
+pub use tclchannel::{Buffering, TclChannel, Translation};
+
+mod stdio;
+pub use stdio::{CallbackWriter, StdRedirect};
+
+mod vars;
+pub use vars::VarFlags;
+
+mod completion;
+pub use completion::{Completion, IntoCompletion};
+
+mod trace;
+pub use self::trace::{TraceCallback, TraceOps, VarTrace};
+
+mod handle;
+pub(crate) use handle::fail_pending_jobs;
+pub use handle::TclInterpHandle;
+
+mod events;
+pub use events::EventFlags;
+
+mod timers;
+pub use timers::TimerToken;
+
+#[cfg(unix)]
+mod filehandler;
+#[cfg(unix)]
+pub use filehandler::FileMask;
+
 struct TclInterpData {
     interp: NonNull<tcl_sys::Tcl_Interp>,
-    commands: HashMap<CString, *mut CommandData>,
+    commands: HashMap<tcl_sys::Tcl_Command, *const CommandData>,
     exit_var_name: String,
+    quit_requested: bool,
+    handle_id: Option<usize>,
 }
 
 /// A wrapper type around a `*Tcl_Interp`.
@@ -45,9 +78,31 @@ struct TclInterpData {
 ///
 /// Any of the methods of this struct that return a `Result` have the possibility to return an
 /// `Err` if the `*Tcl_Interp` is used post-deletion.
+///
+/// The interpreter is deleted once the last `TclInterp` referring to it is dropped. Commands only
+/// hold a weak reference to it, so a command closure should use the interpreter it is given
+/// instead of capturing a clone, which would keep the interpreter alive forever.
 #[derive(Clone)]
 pub struct TclInterp(Rc<Mutex<TclInterpData>>);
 
+/// A weak reference to a `TclInterp`, for data owned by the interpreter itself.
+pub(crate) struct WeakTclInterp {
+    data: Weak<Mutex<TclInterpData>>,
+    ptr: NonNull<tcl_sys::Tcl_Interp>,
+}
+
+impl WeakTclInterp {
+    /// Get back a `TclInterp`, unless the last one is being dropped.
+    pub(crate) fn upgrade(&self) -> Option<TclInterp> {
+        self.data.upgrade().map(TclInterp)
+    }
+
+    /// Get the raw interpreter, which stays valid for as long as Tcl may call into us with it.
+    fn as_ptr(&self) -> *mut tcl_sys::Tcl_Interp {
+        self.ptr.as_ptr()
+    }
+}
+
 impl TclInterp {
     /// Create a new Tcl interpreter.
     ///
@@ -57,17 +112,27 @@ impl TclInterp {
     ///
     /// It also fails if `Tcl_Init()` fails.
     pub fn new() -> Result<Self, TclError> {
+        crate::init_tcl();
+
         unsafe {
             // XXX: Should we move this into its own function and make it "optional"?
             let exit_var_name = format!("exit_var_{}", rand::random::<u64>());
             debug!("Creating exit variable {:?}", exit_var_name);
 
+            let interp_ptr = NonNull::new(tcl_sys::Tcl_CreateInterp())
+                .ok_or_else(|| TclError::new("Tcl_CreateInterp() returned NULL"))?;
+
+            // This keeps the memory of the interpreter around until we're dropped, even if it is
+            // deleted earlier, so that we can always ask whether it was deleted.
+            tcl_sys::Tcl_Preserve(interp_ptr.as_ptr() as tcl_sys::ClientData);
+
             let interp = Rc::new(Mutex::new(TclInterpData {
-                interp: NonNull::new(tcl_sys::Tcl_CreateInterp())
-                    .ok_or_else(|| TclError::new("Tcl_CreateInterp() returned NULL"))?,
+                interp: interp_ptr,
 
                 commands: Default::default(),
                 exit_var_name: exit_var_name.clone(),
+                quit_requested: false,
+                handle_id: None,
             }));
 
             let mut inst = Self(interp);
@@ -94,6 +159,13 @@ impl TclInterp {
         Ok(())
     }
 
+    pub(crate) fn downgrade(&self) -> WeakTclInterp {
+        WeakTclInterp {
+            data: Rc::downgrade(&self.0),
+            ptr: attr!(self.interp),
+        }
+    }
+
     pub fn deleted(&self) -> bool {
         let ptr = attr!(self.interp).as_ptr();
         (unsafe { tcl_sys::Tcl_InterpDeleted(ptr) }) != 0
@@ -101,7 +173,10 @@ impl TclInterp {
 
     fn interp_ptr(&self) -> Result<Preserve<tcl_sys::Tcl_Interp>, TclError> {
         if self.deleted() {
-            return Err(TclError::new("Tried to use interpreter after deletion"));
+            return Err(TclError::with_kind(
+                TclErrorKind::InterpDeleted,
+                "Tried to use interpreter after deletion",
+            ));
         }
 
         Ok(Preserve::new(attr!(self.interp)))
@@ -109,29 +184,60 @@ impl TclInterp {
 
     /// Evaluate a piece of Tcl code given as a string.
     ///
+    /// Code that calls `return`, `break` or `continue` from within a command yields the matching
+    /// `Completion`. Use `Completion::into_result` to treat those as errors.
+    ///
     /// # Errors
-    /// This function fails if `code` contains NUL bytes or if there is an error evaluating the Tcl
-    /// code.
-    pub fn eval(&mut self, code: String) -> Result<String, TclError> {
+    /// This function fails if there is an error evaluating the Tcl code.
+    pub fn eval(&mut self, code: String) -> Result<Completion<String>, TclError> {
+        Ok(self.eval_obj(code)?.map(|obj| obj.to_string()))
+    }
+
+    /// Evaluate a piece of Tcl code given as a string, returning the resulting Tcl object.
+    ///
+    /// Unlike `eval`, this does not force Tcl to build a string representation of the result.
+    ///
+    /// # Errors
+    /// This function fails for the same reasons as `eval`.
+    pub fn eval_obj(&mut self, code: String) -> Result<Completion, TclError> {
         trace!("Evaluating code {:?}", code);
 
-        let c_code =
-            CString::new(code).map_err(|_| TclError::new("code must not contain NUL bytes."))?;
+        let code = mutf8::encode(code.as_bytes());
 
-        self.check_statuscode(unsafe {
-            tcl_sys::Tcl_Eval(self.interp_ptr()?.as_ptr(), c_code.as_ptr())
-        })?;
+        let res = unsafe {
+            tcl_sys::Tcl_EvalEx(
+                self.interp_ptr()?.as_ptr(),
+                code.as_ptr() as *const c_char,
+                code.len() as c_int,
+                0,
+            )
+        };
 
-        let result = self.get_result()?;
-        Ok(result.to_string())
+        self.completion(res)
     }
 
     /// Evaluate a piece of Tcl code given as a list.
     ///
+    /// Like `eval`, this reports `return`, `break` and `continue` through the `Completion`.
+    ///
     /// # Errors
     /// This function fails if any of the given arguments are not convertable to Tcl objects or if
     /// there is an error evaluating the resulting Tcl code.
-    pub fn call<I>(&mut self, it: I) -> Result<String, TclError>
+    pub fn call<I>(&mut self, it: I) -> Result<Completion<String>, TclError>
+    where
+        I: IntoIterator,
+        I::Item: ToTclObj,
+    {
+        Ok(self.call_obj(it)?.map(|obj| obj.to_string()))
+    }
+
+    /// Evaluate a piece of Tcl code given as a list, returning the resulting Tcl object.
+    ///
+    /// Unlike `call`, this does not force Tcl to build a string representation of the result.
+    ///
+    /// # Errors
+    /// This function fails for the same reasons as `call`.
+    pub fn call_obj<I>(&mut self, it: I) -> Result<Completion, TclError>
     where
         I: IntoIterator,
         I::Item: ToTclObj,
@@ -139,12 +245,11 @@ impl TclInterp {
         let objv = Objv::new(it);
         trace!("Calling {:?}", objv);
 
-        self.check_statuscode(unsafe {
+        let res = unsafe {
             tcl_sys::Tcl_EvalObjv(self.interp_ptr()?.as_ptr(), objv.len(), objv.as_ptr(), 0)
-        })?;
+        };
 
-        let result = self.get_result()?;
-        Ok(result.to_string())
+        self.completion(res)
     }
 
     fn get_result(&self) -> Result<TclObj, TclError> {
@@ -161,8 +266,43 @@ impl TclInterp {
         Ok(())
     }
 
+    /// Build an error from the interpreter's result and return options, after a Tcl function
+    /// failed.
     fn get_error(&self) -> Result<TclError, TclError> {
-        Ok(TclError::new(self.get_result()?.to_string()))
+        let message = self.get_result()?.to_string();
+
+        let options = unsafe {
+            tcl_sys::Tcl_GetReturnOptions(self.interp_ptr()?.as_ptr(), tcl_sys::TCL_ERROR as c_int)
+        };
+        let options = NonNull::new(options)
+            .map(TclObj::new)
+            .map(TclDict::from_obj)
+            .ok_or_else(|| TclError::new("Tcl_GetReturnOptions() returned NULL"))??;
+
+        let error_info = options.get("-errorinfo").map(|obj| obj.to_string());
+        let error_code = options.get("-errorcode").map(|obj| obj.to_string());
+        let line = options
+            .get("-errorline")
+            .and_then(|obj| obj.extract::<i32>().ok())
+            .map(|line| line as u32);
+
+        Ok(TclError::script(message, error_info, error_code, line))
+    }
+
+    /// Report an error that happened outside of any script the way Tcl reports a failing `after`
+    /// script, i.e. with `bgerror`.
+    fn background_error(&mut self, err: TclError) {
+        let res = self.set_result(err.message().to_tcl_obj()).and_then(|_| {
+            let interp = self.interp_ptr()?;
+            unsafe {
+                tcl_sys::Tcl_BackgroundException(interp.as_ptr(), tcl_sys::TCL_ERROR as c_int)
+            };
+            Ok(())
+        });
+
+        if res.is_err() {
+            error!("{}", err);
+        }
     }
 
     fn check_statuscode(&self, value: c_int) -> Result<(), TclError> {
@@ -172,6 +312,21 @@ impl TclInterp {
         }
     }
 
+    /// Build the completion for a return code of a Tcl function that leaves its result in the
+    /// interpreter.
+    fn completion(&self, code: c_int) -> Result<Completion, TclError> {
+        let result = self.get_result()?;
+
+        Ok(match code as c_uint {
+            tcl_sys::TCL_OK => Completion::Ok(result),
+            tcl_sys::TCL_ERROR => return Err(self.get_error()?),
+            tcl_sys::TCL_RETURN => Completion::Return(result),
+            tcl_sys::TCL_BREAK => Completion::Break,
+            tcl_sys::TCL_CONTINUE => Completion::Continue,
+            _ => Completion::Other(code, result),
+        })
+    }
+
     /// Split a Tcl list object into its parts.
     ///
     /// # Errors
@@ -202,15 +357,13 @@ impl TclInterp {
     /// Convert a Tcl bool to a Rust bool.
     ///
     /// # Errors
-    /// This function fails if `s` contains NUL bytes or if `s` is not a Tcl bool.
+    /// This function fails if `s` is not a Tcl bool.
     pub fn getboolean(&self, s: String) -> Result<bool, TclError> {
-        let s =
-            CString::new(s).map_err(|_| TclError::new("Argument must not contain NUL bytes."))?;
-
+        let obj = s.to_tcl_obj();
         let mut value: c_int = Default::default();
 
         self.check_statuscode(unsafe {
-            tcl_sys::Tcl_GetBoolean(self.interp_ptr()?.as_ptr(), s.as_ptr(), &mut value)
+            tcl_sys::Tcl_GetBooleanFromObj(self.interp_ptr()?.as_ptr(), obj.as_ptr(), &mut value)
         })?;
 
         Ok(value != 0)
@@ -225,43 +378,55 @@ impl TclInterp {
         unsafe { tcl_sys::Tcl_DeleteInterp(self.interp_ptr()?.as_ptr()) };
         Ok(())
     }
-
-    fn get_var(&self, name: &CStr) -> Result<TclObj, TclError> {
-        let ptr = unsafe {
-            tcl_sys::Tcl_GetVar2Ex(self.interp_ptr()?.as_ptr(), name.as_ptr(), ptr::null(), 0)
-        };
-        NonNull::new(ptr)
-            .ok_or_else(|| TclError::new(format!("Could not get variable with name {:?}", name)))
-            .map(TclObj::new)
-    }
-
-    /// Run the Tcl mainloop.
-    pub fn mainloop(&mut self) -> Result<(), TclError> {
-        let exit_var_name = CString::new(attr!(self.exit_var_name).clone()).unwrap();
-
-        while !self.deleted() && self.get_var(exit_var_name.as_ref())?.to_string() != "true" {
-            let res = unsafe { tcl_sys::Tcl_DoOneEvent(0) };
-            assert_eq!(res, 1);
-        }
-
-        Ok(())
-    }
 }
 
 // We must implement drop on `TclInterpData` and not `TclInterp` because otherwise we try to drop
 // stuff at the same time in different instances and demons spawn.
 impl Drop for TclInterpData {
     fn drop(&mut self) {
+        debug!("Dropping interpreter");
+
+        self.remove_handles();
+        #[cfg(unix)]
+        self.remove_file_handlers();
+
         unsafe {
-            if (tcl_sys::Tcl_InterpDeleted(self.interp.as_ptr())) != 0 {
+            if tcl_sys::Tcl_InterpDeleted(self.interp.as_ptr()) == 0 {
                 tcl_sys::Tcl_DeleteInterp(self.interp.as_ptr());
             }
 
-            self.commands
-                .values()
-                .cloned()
-                .map(|ptr| Box::from_raw(ptr))
-                .for_each(mem::drop);
+            // Tcl only really deletes the interpreter once nobody preserves it anymore, and that
+            // is what runs the deleters of our commands and drops their closures.
+            tcl_sys::Tcl_Release(self.interp.as_ptr() as tcl_sys::ClientData);
+        }
+    }
+}
+
+/// Helpers shared by the tests of several modules.
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// Get the value of the variable `name` as a string.
+    pub fn getvar_string(interp: &TclInterp, name: &str) -> String {
+        interp.getvar(name, VarFlags::NONE).unwrap().to_string()
+    }
+
+    /// Record background errors in the variable `errors`, which `vwait errors` can wait for.
+    pub fn catch_bgerrors(interp: &mut TclInterp) {
+        interp
+            .eval("set errors {}; proc bgerror msg { lappend ::errors $msg }".to_owned())
+            .unwrap();
+    }
+
+    /// Sets a flag when dropped, to tell when a closure that captured it is dropped.
+    pub struct SetOnDrop(pub Rc<Cell<bool>>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.set(true);
         }
     }
 }
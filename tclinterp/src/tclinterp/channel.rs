@@ -1,73 +1,433 @@
-use std::{
-    net::{TcpListener, TcpStream},
-    thread,
-};
+use std::io::{self, Read, Write};
 
-use rand::Rng;
+use super::*;
 
-use crate::tclsocket::TclSocket;
+/// `TCL_CHANNEL_VERSION_5`, which bindgen can't bind because it's a cast.
+const CHANNEL_VERSION_5: usize = 5;
 
-use super::*;
+/// How long to wait before telling Tcl that a watched channel is ready again.
+///
+/// We can't know when an arbitrary stream is ready, so a watched channel is always considered
+/// ready, like Tcl's own in-memory channels do it. Waiting a bit keeps `update` from spinning
+/// forever on a channel with a `fileevent` handler.
+const NOTIFY_DELAY_MS: c_int = 5;
+
+/// Something a Rust channel can read from and write to.
+pub(crate) trait Stream: Read + Write {}
 
-/// Create a two-way communication channel between Rust and Tcl.
-pub fn create_channel(interp: TclInterp) -> Result<(TcpStream, TclSocket), TclError> {
-    let host = "127.0.0.1";
-    let port = rand::thread_rng().gen_range(1024, 8096);
+impl<S: Read + Write> Stream for S {}
 
-    let server = TcpListener::bind((host, port)).map_err(|_| unimplemented!())?;
-    let accept_thread = thread::spawn(move || server.accept().map(|(sock, _)| sock));
+struct ChannelData {
+    stream: Box<dyn Stream>,
+    channel: tcl_sys::Tcl_Channel,
+
+    /// The events Tcl is interested in, as given to the watch proc.
+    watch_mask: c_int,
+    timer: tcl_sys::Tcl_TimerToken,
+}
 
-    let tcl_sock = TclSocket::connect(interp, host, &port.to_string())?;
+impl ChannelData {
+    fn from_instance<'a>(instance_data: tcl_sys::ClientData) -> &'a mut ChannelData {
+        unsafe { &mut *(instance_data as *mut ChannelData) }
+    }
 
-    let rust_sock = accept_thread
-        .join()
-        .map_err(|_| TclError::new("Error joining thread"))?
-        .map_err(|_| TclError::new("Error creating channel"))?;
+    fn cancel_timer(&mut self) {
+        if !self.timer.is_null() {
+            unsafe { tcl_sys::Tcl_DeleteTimerHandler(self.timer) };
+            self.timer = ptr::null_mut();
+        }
+    }
 
-    Ok((rust_sock, tcl_sock))
+    fn schedule_notify(&mut self) {
+        if self.watch_mask != 0 && self.timer.is_null() {
+            self.timer = unsafe {
+                tcl_sys::Tcl_CreateTimerHandler(
+                    NOTIFY_DELAY_MS,
+                    Some(notify_proc),
+                    self as *mut ChannelData as tcl_sys::ClientData,
+                )
+            };
+        }
+    }
+}
+
+/// A `Tcl_ChannelType` is full of pointers, which makes it neither `Send` nor `Sync`.
+struct ChannelType(tcl_sys::Tcl_ChannelType);
+
+// The channel type is never modified and only points to static data.
+unsafe impl Sync for ChannelType {}
+
+static CHANNEL_TYPE: ChannelType = ChannelType(tcl_sys::Tcl_ChannelType {
+    typeName: b"rust\0" as *const u8 as *const c_char,
+    version: CHANNEL_VERSION_5 as tcl_sys::Tcl_ChannelTypeVersion,
+    closeProc: Some(close_proc),
+    inputProc: Some(input_proc),
+    outputProc: Some(output_proc),
+    seekProc: None,
+    setOptionProc: None,
+    getOptionProc: None,
+    watchProc: Some(watch_proc),
+    getHandleProc: Some(get_handle_proc),
+    close2Proc: None,
+    blockModeProc: None,
+    flushProc: None,
+    handlerProc: None,
+    wideSeekProc: None,
+    threadActionProc: None,
+    truncateProc: None,
+});
+
+/// Get the errno Tcl expects for an I/O error.
+fn errno(err: &io::Error) -> c_int {
+    err.raw_os_error().unwrap_or_else(|| match err.kind() {
+        io::ErrorKind::WouldBlock => libc::EAGAIN,
+        io::ErrorKind::Interrupted => libc::EINTR,
+        io::ErrorKind::InvalidInput => libc::EINVAL,
+        _ => libc::EIO,
+    })
+}
+
+/// Run a read or write on the stream, converting the result to what Tcl expects.
+fn transfer(what: &str, error_code: *mut c_int, f: impl FnOnce() -> io::Result<usize>) -> c_int {
+    let res = catch_panic(what, || Ok(f()));
+
+    match res {
+        Ok(Ok(n)) => n as c_int,
+        Ok(Err(err)) => {
+            debug!("{} failed: {}", what, err);
+            unsafe { *error_code = errno(&err) };
+            -1
+        }
+        Err(err) => {
+            error!("{}", err);
+            unsafe { *error_code = libc::EIO };
+            -1
+        }
+    }
+}
+
+extern "C" fn input_proc(
+    instance_data: tcl_sys::ClientData,
+    buf: *mut c_char,
+    to_read: c_int,
+    error_code: *mut c_int,
+) -> c_int {
+    let data = ChannelData::from_instance(instance_data);
+    let buf = unsafe { slice::from_raw_parts_mut(buf as *mut u8, to_read as usize) };
+
+    transfer("Channel read", error_code, || data.stream.read(buf))
+}
+
+extern "C" fn output_proc(
+    instance_data: tcl_sys::ClientData,
+    buf: *const c_char,
+    to_write: c_int,
+    error_code: *mut c_int,
+) -> c_int {
+    let data = ChannelData::from_instance(instance_data);
+    let buf = unsafe { slice::from_raw_parts(buf as *const u8, to_write as usize) };
+
+    transfer("Channel write", error_code, || {
+        // Tcl does its own buffering, so whatever it gives us is meant to go out now.
+        let n = data.stream.write(buf)?;
+        data.stream.flush()?;
+        Ok(n)
+    })
+}
+
+extern "C" fn close_proc(
+    instance_data: tcl_sys::ClientData,
+    _interp: *mut tcl_sys::Tcl_Interp,
+) -> c_int {
+    // This takes back the data we gave to Tcl, so the stream is dropped at the end.
+    let mut data = unsafe { Box::from_raw(instance_data as *mut ChannelData) };
+    debug!("Closing Rust channel");
+
+    data.cancel_timer();
+    let res = catch_panic("Channel close", move || {
+        let res = data.stream.flush();
+        mem::drop(data);
+        Ok(res)
+    });
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(err)) => errno(&err),
+        Err(err) => {
+            error!("{}", err);
+            libc::EIO
+        }
+    }
+}
+
+extern "C" fn watch_proc(instance_data: tcl_sys::ClientData, mask: c_int) {
+    let data = ChannelData::from_instance(instance_data);
+    trace!("Watching Rust channel with mask {}", mask);
+
+    data.watch_mask = mask;
+    if mask == 0 {
+        data.cancel_timer();
+    } else {
+        data.schedule_notify();
+    }
+}
+
+extern "C" fn notify_proc(client_data: tcl_sys::ClientData) {
+    let data = ChannelData::from_instance(client_data);
+    data.timer = ptr::null_mut();
+
+    // The handlers may close the channel, after which `data` is gone, so schedule the next
+    // notification first and don't touch it afterwards. Closing cancels the timer.
+    data.schedule_notify();
+    let (channel, mask) = (data.channel, data.watch_mask);
+
+    // `Tcl_NotifyChannel` runs the `fileevent` scripts, which report their own errors.
+    unsafe { tcl_sys::Tcl_NotifyChannel(channel, mask) };
+}
+
+extern "C" fn get_handle_proc(
+    _instance_data: tcl_sys::ClientData,
+    _direction: c_int,
+    _handle: *mut tcl_sys::ClientData,
+) -> c_int {
+    // There's no OS handle behind an arbitrary stream.
+    tcl_sys::TCL_ERROR as c_int
+}
+
+/// Create a channel backed by `stream` that supports the operations in `mask`, which isn't
+/// registered in any interpreter yet.
+pub(crate) fn new_channel(stream: Box<dyn Stream>, mask: c_int) -> tcl_sys::Tcl_Channel {
+    let data = Box::into_raw(Box::new(ChannelData {
+        stream,
+        channel: ptr::null_mut(),
+        watch_mask: 0,
+        timer: ptr::null_mut(),
+    }));
+
+    // The address of the data is unique for as long as the channel exists.
+    let name = CString::new(format!("rust{:x}", data as usize)).unwrap();
+    debug!("Creating Rust channel {:?}", name);
+
+    unsafe {
+        let channel = tcl_sys::Tcl_CreateChannel(
+            &CHANNEL_TYPE.0,
+            name.as_ptr(),
+            data as tcl_sys::ClientData,
+            mask,
+        );
+        (*data).channel = channel;
+        channel
+    }
+}
+
+impl TclInterp {
+    /// Make `stream` available to scripts as a channel, returning the channel's name.
+    ///
+    /// The channel works with `puts`, `gets`, `read`, `fconfigure`, `fileevent` and the other
+    /// channel commands, and it's closed, dropping `stream`, with `close` or when the interpreter
+    /// is deleted. Tcl buffers and translates the data like for any other channel, so use
+    /// `fconfigure $chan -translation binary` for binary data.
+    ///
+    /// The stream is used as is, so reading from the channel blocks if reading from the stream
+    /// does, and returning 0 from `read` means end of file. Since there's no way to know when an
+    /// arbitrary stream is ready, `fileevent` handlers are called whenever the event loop runs.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn create_channel<S>(&mut self, stream: S) -> Result<String, TclError>
+    where
+        S: Read + Write + 'static,
+    {
+        let interp = self.interp_ptr()?;
+
+        let channel = new_channel(
+            Box::new(stream),
+            (tcl_sys::TCL_READABLE | tcl_sys::TCL_WRITABLE) as c_int,
+        );
+        unsafe { tcl_sys::Tcl_RegisterChannel(interp.as_ptr(), channel) };
+
+        let name = unsafe { CStr::from_ptr(tcl_sys::Tcl_GetChannelName(channel)) };
+        Ok(name.to_string_lossy().into_owned())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::{BufRead, BufReader, Read, Write};
+    use std::{
+        cell::RefCell,
+        collections::VecDeque,
+        io::Cursor,
+        time::{Duration, Instant},
+    };
 
     use super::*;
+    use crate::tclobj::TclByteArray;
+
+    /// An in-memory pipe. Whatever is written to it can be read back from it and from its clones.
+    #[derive(Clone, Default)]
+    struct Pipe(Rc<RefCell<VecDeque<u8>>>);
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut data = self.0.borrow_mut();
+            let n = buf.len().min(data.len());
+            for (dst, src) in buf.iter_mut().zip(data.drain(..n)) {
+                *dst = src;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn eval(interp: &mut TclInterp, code: &str) -> String {
+        interp
+            .eval(code.to_owned())
+            .and_then(Completion::into_result)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_gets() {
+        let mut interp = TclInterp::new().unwrap();
+        let chan = interp
+            .create_channel(Cursor::new(b"hello\nworld\n".to_vec()))
+            .unwrap();
+        interp.setvar("chan", chan, VarFlags::NONE).unwrap();
+
+        assert_eq!(eval(&mut interp, "gets $chan"), "hello");
+        assert_eq!(eval(&mut interp, "gets $chan"), "world");
+        assert_eq!(eval(&mut interp, "gets $chan"), "");
+        assert_eq!(eval(&mut interp, "eof $chan"), "1");
+        eval(&mut interp, "close $chan");
+    }
+
+    #[test]
+    fn test_puts() {
+        let mut interp = TclInterp::new().unwrap();
+        let pipe = Pipe::default();
+        let chan = interp.create_channel(pipe.clone()).unwrap();
+        interp.setvar("chan", chan, VarFlags::NONE).unwrap();
+
+        eval(&mut interp, "puts $chan hello; puts -nonewline $chan world");
+        assert!(pipe.0.borrow().is_empty());
+
+        eval(&mut interp, "flush $chan");
+        assert_eq!(
+            pipe.0.borrow().iter().cloned().collect::<Vec<_>>(),
+            b"hello\nworld"
+        );
+
+        // Tcl reads what we wrote through the same pipe.
+        eval(&mut interp, "puts $chan {}; flush $chan");
+        assert_eq!(eval(&mut interp, "gets $chan"), "hello");
+        assert_eq!(eval(&mut interp, "gets $chan"), "world");
+    }
 
     #[test]
-    fn test_creation() {
-        assert!(create_channel(TclInterp::new().unwrap()).is_ok());
+    fn test_binary() {
+        let mut interp = TclInterp::new().unwrap();
+        let pipe = Pipe::default();
+        let chan = interp.create_channel(pipe.clone()).unwrap();
+        interp.setvar("chan", chan, VarFlags::NONE).unwrap();
+
+        let data = (0..=255).collect::<Vec<u8>>();
+        pipe.0.borrow_mut().extend(&data);
+
+        eval(&mut interp, "fconfigure $chan -translation binary");
+        let read = interp
+            .eval_obj("read $chan 256".to_owned())
+            .and_then(Completion::into_result)
+            .map(TclByteArray::from_obj)
+            .unwrap();
+        assert_eq!(read.as_bytes(), &data[..]);
+
+        interp
+            .setvar("data", TclByteArray::new(&data), VarFlags::NONE)
+            .unwrap();
+        eval(&mut interp, "puts -nonewline $chan $data; flush $chan");
+        assert_eq!(pipe.0.borrow().iter().cloned().collect::<Vec<_>>(), data);
     }
 
     #[test]
-    fn test_tclsock_recv_data() {
-        let (mut rust, mut tcl) = create_channel(TclInterp::new().unwrap()).unwrap();
-        write!(rust, "hello, world").unwrap();
+    fn test_close_drops_stream() {
+        let mut interp = TclInterp::new().unwrap();
+        let pipe = Pipe::default();
 
-        let mut data: String = Default::default();
-        tcl.read_to_string(&mut data).unwrap();
+        let chan = interp.create_channel(pipe.clone()).unwrap();
+        assert_eq!(Rc::strong_count(&pipe.0), 2);
+        interp.call(&["close", &chan]).unwrap();
+        assert_eq!(Rc::strong_count(&pipe.0), 1);
 
-        assert_eq!(data, "hello, world");
+        let chan = interp.create_channel(pipe.clone()).unwrap();
+        interp.setvar("chan", chan, VarFlags::NONE).unwrap();
+        eval(&mut interp, "puts -nonewline $chan unflushed");
+        mem::drop(interp);
+        assert_eq!(Rc::strong_count(&pipe.0), 1);
+        assert_eq!(
+            pipe.0.borrow().iter().cloned().collect::<Vec<_>>(),
+            b"unflushed"
+        );
     }
 
     #[test]
-    fn test_tclsock_recv_empty() {
-        let (_, mut tcl) = create_channel(TclInterp::new().unwrap()).unwrap();
+    fn test_errors() {
+        struct Broken;
+
+        impl Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            }
+        }
+
+        impl Write for Broken {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                panic!("write")
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut interp = TclInterp::new().unwrap();
+        let chan = interp.create_channel(Broken).unwrap();
+        interp.setvar("chan", chan, VarFlags::NONE).unwrap();
 
-        let mut data: String = Default::default();
-        tcl.read_to_string(&mut data).unwrap();
+        let err = interp.eval("gets $chan".to_owned()).unwrap_err();
+        assert!(err.message().starts_with("error reading"), "{}", err);
 
-        assert_eq!(data, "");
+        let err = interp
+            .eval("puts $chan x; flush $chan".to_owned())
+            .unwrap_err();
+        assert!(err.message().starts_with("error flushing"), "{}", err);
     }
 
     #[test]
-    fn test_tclsock_send() {
-        let (rust, mut tcl) = create_channel(TclInterp::new().unwrap()).unwrap();
-        write!(tcl, "hello, world\n").unwrap();
-        tcl.flush().unwrap();
+    fn test_fileevent() {
+        let mut interp = TclInterp::new().unwrap();
+        let pipe = Pipe::default();
+        let chan = interp.create_channel(pipe.clone()).unwrap();
+        interp.setvar("chan", chan, VarFlags::NONE).unwrap();
 
-        let mut data: String = Default::default();
-        BufReader::new(rust).read_line(&mut data).unwrap();
+        pipe.0.borrow_mut().extend(b"line\n");
+        eval(
+            &mut interp,
+            "set lines {}; fileevent $chan readable { lappend lines [gets $chan]; fileevent $chan readable {} }",
+        );
 
-        assert_eq!(data, "hello, world\n");
+        interp
+            .run_until(Instant::now() + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(eval(&mut interp, "set lines"), "line");
     }
 }
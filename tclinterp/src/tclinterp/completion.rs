@@ -0,0 +1,267 @@
+use super::*;
+
+/// How a Tcl script or command completed.
+///
+/// Besides succeeding or failing, Tcl code can complete with one of the exceptional codes used by
+/// `return`, `break` and `continue`, or with an application-defined code. Rust commands can
+/// return a `Completion` to implement control-flow constructs, and `TclInterp::eval` and friends
+/// return one so that such codes reach the caller.
+#[derive(Debug, Clone)]
+pub enum Completion<T = TclObj> {
+    /// The code completed normally (`TCL_OK`) with the given result.
+    Ok(T),
+
+    /// The code failed (`TCL_ERROR`).
+    ///
+    /// Methods of `TclInterp` report errors through their `Err` variant instead, so this only
+    /// shows up in completions returned by commands.
+    Error(TclError),
+
+    /// The code invoked `return` (`TCL_RETURN`) with the given result.
+    Return(T),
+
+    /// The code invoked `break` (`TCL_BREAK`).
+    Break,
+
+    /// The code invoked `continue` (`TCL_CONTINUE`).
+    Continue,
+
+    /// The code completed with an application-defined code and the given result.
+    Other(c_int, T),
+}
+
+impl<T> Completion<T> {
+    /// The Tcl return code for this completion.
+    pub fn code(&self) -> c_int {
+        (match self {
+            Completion::Ok(_) => tcl_sys::TCL_OK,
+            Completion::Error(_) => tcl_sys::TCL_ERROR,
+            Completion::Return(_) => tcl_sys::TCL_RETURN,
+            Completion::Break => tcl_sys::TCL_BREAK,
+            Completion::Continue => tcl_sys::TCL_CONTINUE,
+            Completion::Other(code, _) => return *code,
+        }) as c_int
+    }
+
+    /// Convert the result carried by this completion, if any.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Completion<U> {
+        match self {
+            Completion::Ok(value) => Completion::Ok(f(value)),
+            Completion::Error(err) => Completion::Error(err),
+            Completion::Return(value) => Completion::Return(f(value)),
+            Completion::Break => Completion::Break,
+            Completion::Continue => Completion::Continue,
+            Completion::Other(code, value) => Completion::Other(code, f(value)),
+        }
+    }
+
+    /// Get the result of a normal completion, treating every other completion as an error.
+    ///
+    /// This is what a caller that has no use for `return`, `break` or `continue` wants.
+    ///
+    /// # Errors
+    /// This function fails for anything but `Completion::Ok`.
+    pub fn into_result(self) -> Result<T, TclError> {
+        match self {
+            Completion::Ok(value) => Ok(value),
+            Completion::Error(err) => Err(err),
+            Completion::Return(_) => Err(TclError::new("invoked \"return\" outside of a proc")),
+            Completion::Break => Err(TclError::new("invoked \"break\" outside of a loop")),
+            Completion::Continue => Err(TclError::new("invoked \"continue\" outside of a loop")),
+            Completion::Other(code, _) => Err(TclError::new(format!(
+                "command returned bad code: {}",
+                code
+            ))),
+        }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Completion<T> {
+    /// Completions are equal if they have the same code and result.
+    ///
+    /// Errors are compared by their message.
+    fn eq(&self, other: &Completion<T>) -> bool {
+        match (self, other) {
+            (Completion::Ok(a), Completion::Ok(b)) => a == b,
+            (Completion::Error(a), Completion::Error(b)) => a.message() == b.message(),
+            (Completion::Return(a), Completion::Return(b)) => a == b,
+            (Completion::Break, Completion::Break) => true,
+            (Completion::Continue, Completion::Continue) => true,
+            (Completion::Other(code_a, a), Completion::Other(code_b, b)) => {
+                code_a == code_b && a == b
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Something a command created by `TclInterp::createcommand` can return.
+///
+/// This is implemented for `Completion` itself and for any `Result` whose `Ok` variant can be
+/// converted to a Tcl object and whose `Err` variant can be converted to a `TclError`.
+pub trait IntoCompletion {
+    fn into_completion(self) -> Completion;
+}
+
+impl IntoCompletion for Completion {
+    fn into_completion(self) -> Completion {
+        self
+    }
+}
+
+impl<R, E> IntoCompletion for Result<R, E>
+where
+    R: ToTclObj,
+    E: Into<TclError>,
+{
+    fn into_completion(self) -> Completion {
+        match self {
+            Ok(value) => Completion::Ok(value.to_tcl_obj()),
+            Err(err) => Completion::Error(err.into()),
+        }
+    }
+}
+
+impl<E> IntoCompletion for Result<Completion, E>
+where
+    E: Into<TclError>,
+{
+    fn into_completion(self) -> Completion {
+        self.unwrap_or_else(|err| Completion::Error(err.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create `repeat n body`, which runs `body` `n` times and supports `break` and `continue`.
+    fn create_repeat(interp: &mut TclInterp) {
+        interp
+            .createcommand("repeat", |interp, args| {
+                let count = args[0].extract::<i32>()?;
+
+                for _ in 0..count {
+                    match interp.eval(args[1].to_string())? {
+                        Completion::Ok(_) | Completion::Continue => {}
+                        Completion::Break => break,
+                        completion => return Ok(completion.map(|s| s.to_tcl_obj())),
+                    }
+                }
+
+                Ok::<_, TclError>(Completion::Ok("".to_tcl_obj()))
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let mut interp = TclInterp::new().unwrap();
+        create_repeat(&mut interp);
+
+        let res = interp
+            .eval("set x 0; repeat 10 { incr x; if {$x == 3} break }; set x".to_owned())
+            .unwrap();
+        assert_eq!(res, Completion::Ok("3".to_owned()));
+
+        let res = interp
+            .eval(
+                "set x 0; set y 0; repeat 4 { incr x; if {$x % 2} continue; incr y }; set y"
+                    .to_owned(),
+            )
+            .unwrap();
+        assert_eq!(res, Completion::Ok("2".to_owned()));
+    }
+
+    #[test]
+    fn test_return_through_command() {
+        let mut interp = TclInterp::new().unwrap();
+        create_repeat(&mut interp);
+
+        let res = interp
+            .eval("proc p {} { repeat 5 { return early }; return late }; p".to_owned())
+            .unwrap();
+        assert_eq!(res, Completion::Ok("early".to_owned()));
+    }
+
+    #[test]
+    fn test_command_completions() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("stop", |_, _| Completion::Break)
+            .unwrap();
+        interp
+            .createcommand("give", |_, args| Completion::Return(args[0].clone()))
+            .unwrap();
+        interp
+            .createcommand("custom", |_, _| {
+                Completion::Other(42, "custom".to_tcl_obj())
+            })
+            .unwrap();
+
+        let res = interp
+            .eval("set n 0; foreach i {1 2 3} { incr n; stop }; set n".to_owned())
+            .unwrap();
+        assert_eq!(res, Completion::Ok("1".to_owned()));
+
+        let res = interp
+            .eval("proc p {} { give 7; return 8 }; p".to_owned())
+            .unwrap();
+        assert_eq!(res, Completion::Ok("7".to_owned()));
+
+        let res = interp
+            .eval("list [catch custom msg] $msg".to_owned())
+            .unwrap();
+        assert_eq!(res, Completion::Ok("42 custom".to_owned()));
+    }
+
+    #[test]
+    fn test_nested_codes_reach_caller() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("probe", |interp, args| {
+                let completion = interp.call_obj(args)?;
+                Ok::<_, TclError>(completion.code())
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp.eval("probe break".to_owned()).unwrap(),
+            Completion::Ok("3".to_owned())
+        );
+        assert_eq!(
+            interp.eval("probe continue".to_owned()).unwrap(),
+            Completion::Ok("4".to_owned())
+        );
+        assert_eq!(
+            interp.eval("probe return x".to_owned()).unwrap(),
+            Completion::Ok("2".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_top_level_codes() {
+        let mut interp = TclInterp::new().unwrap();
+
+        // Outside of any command, Tcl itself turns stray exceptional codes into errors.
+        let err = interp.eval("break".to_owned()).unwrap_err();
+        assert_eq!(err.message(), "invoked \"break\" outside of a loop");
+
+        assert_eq!(
+            interp.eval("return hi".to_owned()).unwrap(),
+            Completion::Ok("hi".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_into_result() {
+        assert_eq!(Completion::Ok(1).into_result().unwrap(), 1);
+        assert_eq!(
+            Completion::<i32>::Break
+                .into_result()
+                .unwrap_err()
+                .message(),
+            "invoked \"break\" outside of a loop"
+        );
+    }
+}
@@ -1,122 +1,215 @@
-use std::{any::Any, os::raw::*};
+use std::{cell::Cell, os::raw::*};
 
 use super::*;
 
-pub type Command = fn(&CommandData, &[&CStr]) -> Result<TclObj, TclObj>;
+/// The type-erased closure behind a command created with `TclInterp::createcommand`.
+type Command = dyn Fn(&mut TclInterp, &[TclObj]) -> Completion;
 
 pub struct CommandData {
-    pub interp: TclInterp,
-    pub name: CString,
-    pub cmd: Command,
-    pub data: Box<Any>,
+    // A strong reference here would keep the interpreter alive for as long as the command exists,
+    // which is as long as the interpreter exists.
+    interp: WeakTclInterp,
+    token: Cell<tcl_sys::Tcl_Command>,
+    cmd: Box<Command>,
+}
+
+/// Get the current name of a command, which changes when a script renames it.
+fn command_name(interp: *mut tcl_sys::Tcl_Interp, token: tcl_sys::Tcl_Command) -> String {
+    let name = unsafe { CStr::from_ptr(tcl_sys::Tcl_GetCommandName(interp, token)) };
+    mutf8::decode(name.to_bytes()).into_owned()
 }
 
 extern "C" fn cmd_callback(
     client_data: *mut c_void,
-    _interp: *mut tcl_sys::Tcl_Interp,
-    argc: c_int,
-    argv: *mut *const c_char,
+    interp_ptr: *mut tcl_sys::Tcl_Interp,
+    objc: c_int,
+    objv: *const *mut tcl_sys::Tcl_Obj,
 ) -> c_int {
-    // XXX: We might need to forget this!!!
-    let client_data = unsafe { &mut *(client_data as *mut CommandData) };
-    trace!("Calling command {:?}", client_data.name);
-
-    let args = unsafe {
-        slice::from_raw_parts(argv, argc as usize)
-            .iter()
-            .skip(1)
-            .map(|&s| CStr::from_ptr(s))
-            .collect::<Vec<_>>()
+    // The command may delete itself while it runs, so keep the data alive until we're done.
+    let client_data = unsafe {
+        let tcl_ref = Rc::from_raw(client_data as *const CommandData);
+        let our_ref = tcl_ref.clone();
+        mem::forget(tcl_ref);
+        our_ref
     };
 
-    let res = (client_data.cmd)(&client_data, &args);
+    // Looking up the name is only worth it when something is going to show it.
+    let token = client_data.token.get();
+    let name = || command_name(interp_ptr, token);
+    trace!("Calling command {:?}", name());
 
-    match res {
-        Ok(value) => {
-            if !client_data.interp.deleted() {
-                client_data
-                    .interp
-                    .set_result(value)
-                    .expect("Could not set successful result from command");
-            }
-
-            tcl_sys::TCL_OK as c_int
+    // Commands can only run while someone is using the interpreter, so this should never fail.
+    let mut interp = match client_data.interp.upgrade() {
+        Some(interp) => interp,
+        None => {
+            let msg = format!("Command {:?} called while dropping the interpreter", name());
+            unsafe { tcl_sys::Tcl_SetObjResult(interp_ptr, msg.to_tcl_obj().as_ptr()) };
+            return tcl_sys::TCL_ERROR as c_int;
         }
+    };
+    let mut cmd_interp = interp.clone();
 
-        Err(value) => {
-            if !client_data.interp.deleted() {
-                client_data
-                    .interp
-                    .set_result(value)
-                    .expect("Could not set failing result from command")
-            }
-            tcl_sys::TCL_ERROR as c_int
+    // `client_data` is moved into the closure because dropping it may drop the command itself.
+    let res = catch_panic_with(
+        || format!("Command {:?}", name()),
+        move || {
+            let args = unsafe { slice::from_raw_parts(objv, objc as usize) }
+                .iter()
+                .skip(1)
+                .map(|&ptr| TclObj::new(NonNull::new(ptr).expect("Tcl passed a NULL argument")))
+                .collect::<Vec<_>>();
+
+            Ok((client_data.cmd)(&mut cmd_interp, &args))
+        },
+    );
+
+    let completion = res.unwrap_or_else(Completion::Error);
+    let code = completion.code();
+
+    let (value, err) = match completion {
+        Completion::Ok(value) | Completion::Return(value) | Completion::Other(_, value) => {
+            (value, None)
         }
+        Completion::Error(err) => (err.message().to_tcl_obj(), Some(err)),
+        Completion::Break | Completion::Continue => ("".to_tcl_obj(), None),
+    };
+
+    // Whatever a script the command evaluated left behind is not part of the command's error.
+    if err.is_some() && !interp.deleted() {
+        unsafe { tcl_sys::Tcl_ResetResult(interp_ptr) };
+    }
+
+    // This only fails if the command deleted the interpreter, in which case nobody is going to
+    // look at the result anyway.
+    if interp.set_result(value).is_err() {
+        debug!("Dropping result of command {:?}", name());
+    } else if let Some(err) = err {
+        set_error_options(interp_ptr, &err);
+    }
+
+    code
+}
+
+/// Pass on the error code and stack trace of an error returned by a command, e.g. one that came
+/// from a script the command evaluated, which Tcl then adds to.
+fn set_error_options(interp_ptr: *mut tcl_sys::Tcl_Interp, err: &TclError) {
+    if let Some(error_info) = err.error_info() {
+        // Tcl starts the stack trace with the result, which is the message.
+        let rest = if error_info.starts_with(err.message()) {
+            error_info[err.message().len()..].to_owned()
+        } else {
+            format!("\n{}", error_info)
+        };
+
+        let rest = mutf8::to_cstring(&rest);
+        unsafe { tcl_sys::Tcl_AddObjErrorInfo(interp_ptr, rest.as_ptr(), -1) };
+    }
+
+    if let Some(error_code) = err.error_code() {
+        let error_code = error_code.to_tcl_obj();
+        unsafe { tcl_sys::Tcl_SetObjErrorCode(interp_ptr, error_code.as_ptr()) };
     }
 }
 
 extern "C" fn cmd_deleter(client_data: *mut c_void) {
-    let client_data = unsafe { &mut *(client_data as *mut CommandData) };
-    debug!("Deleting command {:?}", client_data.name);
+    // This takes back the reference we gave to Tcl, so the closure and everything it captured
+    // are dropped at the end of this function (unless the command is still running).
+    let client_data = unsafe { Rc::from_raw(client_data as *const CommandData) };
 
-    let cmd_name = &client_data.name;
-    let interp = &mut client_data.interp;
+    let token = client_data.token.get();
+    let what = format!(
+        "Deleter of command {:?}",
+        command_name(client_data.interp.as_ptr(), token)
+    );
+    debug!("Running {}", what);
+
+    let res = catch_panic(&what, || {
+        // If the interpreter is being dropped, so is the registry.
+        if let Some(interp) = client_data.interp.upgrade() {
+            let cmd = attr!(interp.commands).remove(&token);
+            assert!(cmd.is_some());
+        }
+
+        mem::drop(client_data);
+        Ok(())
+    });
 
-    let cmd = attr!(interp.commands).remove(cmd_name);
-    assert!(cmd.is_some());
+    // Tcl has no way for a deleter to report an error.
+    if let Err(err) = res {
+        error!("{}", err);
+    }
 }
 
 impl TclInterp {
-    pub fn createcommand(
-        &mut self,
-        name: &str,
-        data: Box<Any>,
-        cmd: Command,
-    ) -> Result<(), TclError> {
-        let name =
-            CString::new(name).map_err(|_| TclError::new("name must not contain NUL bytes."))?;
-
+    /// Create a Tcl command named `name` that calls `cmd` with its arguments.
+    ///
+    /// The closure receives the interpreter and the arguments (without the command name), and
+    /// whatever it returns becomes the result of the command. It can return a `Result` or, to
+    /// implement control flow, a `Completion`. The arguments are the very objects Tcl passed to
+    /// the command, so lists, numbers and byte arrays keep their internal representation.
+    ///
+    /// The closure is dropped, along with anything it captured, when the command is deleted,
+    /// whether by `deletecommand` or by a script. Scripts may also rename the command freely.
+    ///
+    /// The command may end up calling itself, e.g. by evaluating a script or running the event
+    /// loop, so the closure can't take its state mutably. Use a `Cell` or `RefCell` for that.
+    ///
+    /// If the closure panics, the panic is caught and the command fails with the panic message.
+    ///
+    /// # Errors
+    /// This function fails if a command created by this function already has the name `name`.
+    pub fn createcommand<F, C>(&mut self, name: &str, cmd: F) -> Result<(), TclError>
+    where
+        F: Fn(&mut TclInterp, &[TclObj]) -> C + 'static,
+        C: IntoCompletion,
+    {
         debug!("Creating command {:?}", name);
         debug!("Commands: {:?}", attr!(self.commands));
 
-        if attr!(self.commands).contains_key(&name) {
+        // Look the name up in Tcl rather than in our registry, which doesn't know about renames.
+        let existing = unsafe {
+            tcl_sys::Tcl_GetCommandFromObj(self.interp_ptr()?.as_ptr(), name.to_tcl_obj().as_ptr())
+        };
+        if attr!(self.commands).contains_key(&existing) {
             return Err(TclError::new(format!(
                 "Command with name {:?} already exists.",
                 name
             )));
         }
 
-        let command_data = CommandData {
-            interp: self.clone(),
-            name: name.clone(),
-            cmd,
-            data,
-        };
-        let command_data = Box::into_raw(Box::new(command_data)) as *mut c_void;
+        let name = mutf8::to_cstring(name);
 
-        let res = unsafe {
-            tcl_sys::Tcl_CreateCommand(
+        let command_data = Rc::new(CommandData {
+            interp: self.downgrade(),
+            token: Cell::new(ptr::null_mut()),
+            cmd: Box::new(move |interp, args| cmd(interp, args).into_completion()),
+        });
+        let client_data = Rc::into_raw(command_data.clone());
+
+        let token = unsafe {
+            tcl_sys::Tcl_CreateObjCommand(
                 self.interp_ptr()?.as_ptr(),
                 name.as_ptr(),
                 Some(cmd_callback),
-                command_data,
+                client_data as *mut c_void,
                 Some(cmd_deleter),
             )
         };
 
-        if res.is_null() {
-            return Err(TclError::new("Tcl_CreateCommand returned NULL"));
+        if token.is_null() {
+            mem::drop(unsafe { Rc::from_raw(client_data) });
+            return Err(TclError::new("Tcl_CreateObjCommand returned NULL"));
         }
 
-        let old_cmd = attr!(self.commands).insert(name, command_data as *mut CommandData);
+        command_data.token.set(token);
+        let old_cmd = attr!(self.commands).insert(token, client_data);
         assert!(old_cmd.is_none());
 
         Ok(())
     }
 
     pub fn deletecommand(&mut self, name: &str) -> Result<(), TclError> {
-        let name =
-            CString::new(name).map_err(|_| TclError::new("name must not contain NUL bytes."))?;
+        let name = mutf8::to_cstring(name);
 
         let res = unsafe { tcl_sys::Tcl_DeleteCommand(self.interp_ptr()?.as_ptr(), name.as_ptr()) };
 
@@ -136,55 +229,489 @@ impl TclInterp {
 mod tests {
     use super::*;
 
-    // TODO: Add tests for a few more things.
+    use std::cell::Cell;
+
+    use crate::{
+        tclinterp::test_utils::SetOnDrop,
+        tclobj::{TclByteArray, TclList},
+    };
 
     #[test]
     fn test_createcommand_data() {
         let mut interp = TclInterp::new().unwrap();
+        let data = "bar".to_string();
         interp
-            .createcommand("foo", Box::new("bar".to_string()), |data, _args| {
-                data.data
-                    .downcast_ref::<String>()
-                    .map(|s| s.to_tcl_obj())
-                    .ok_or_else(|| unreachable!())
-            })
+            .createcommand("foo", move |_, _| Ok::<_, TclError>(data.clone()))
             .unwrap();
-        assert_eq!(interp.eval("foo".to_string()).unwrap(), "bar");
+        assert_eq!(
+            interp
+                .eval("foo".to_string())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "bar"
+        );
     }
 
     #[test]
     fn test_createcommand_args() {
         let mut interp = TclInterp::new().unwrap();
         interp
-            .createcommand("ham", Box::new("unused"), |data, args| {
-                assert_eq!(data.data.downcast_ref::<&str>(), Some(&"unused"));
-
-                args.into_iter()
-                    .map(|s| s.to_str().to_owned())
-                    .collect::<Result<Vec<_>, _>>()
-                    .or_else(|_| unreachable!())
-                    .map(|v| v.join(" ").to_tcl_obj())
+            .createcommand("ham", |_, args| {
+                let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+                Ok::<_, TclError>(args.join(" "))
             })
             .unwrap();
         assert_eq!(
             interp
                 .eval("ham spam ham spam spam ham ham spam".to_string())
+                .and_then(Completion::into_result)
                 .unwrap(),
             "spam ham spam spam ham ham spam"
         );
     }
 
+    #[test]
+    fn test_createcommand_objects() {
+        let mut interp = TclInterp::new().unwrap();
+
+        let list = vec![1, 2, 3].to_tcl_obj();
+        let bytes = TclByteArray::new(b"\xFF\x00").to_tcl_obj();
+        let ptrs = vec![list.as_ptr(), bytes.as_ptr()];
+
+        interp
+            .createcommand("same", move |_, args| {
+                let got = args.iter().map(TclObj::as_ptr).collect::<Vec<_>>();
+                Ok::<_, TclError>(got == ptrs)
+            })
+            .unwrap();
+
+        let res = interp
+            .call_obj(&["same".to_tcl_obj(), list, bytes])
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(res.to_string(), "1");
+    }
+
+    #[test]
+    fn test_createcommand_internal_rep() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("describe", |_, args| {
+                let type_ptr = unsafe { (*args[0].as_ptr()).typePtr };
+                assert!(!type_ptr.is_null());
+
+                let list = TclList::from_obj(args[0].clone())?;
+                let name = unsafe { CStr::from_ptr((*type_ptr).name) };
+                Ok::<_, TclError>((name.to_str().unwrap(), list.len()))
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .eval("describe [list a b c]".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "list 3"
+        );
+    }
+
+    #[test]
+    fn test_createcommand_state() {
+        let mut interp = TclInterp::new().unwrap();
+        let count = Cell::new(0);
+        interp
+            .createcommand("incr_count", move |_, _| {
+                count.set(count.get() + 1);
+                Ok::<_, TclError>(count.get())
+            })
+            .unwrap();
+
+        interp
+            .eval("incr_count; incr_count".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(
+            interp
+                .eval("incr_count".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "3"
+        );
+    }
+
+    #[test]
+    fn test_createcommand_recursive() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("fact", |interp, args| {
+                let n = args[0].extract::<i64>()?;
+                if n <= 1 {
+                    return Ok(1);
+                }
+
+                let rest = interp
+                    .call(&["fact".to_owned(), (n - 1).to_string()])?
+                    .into_result()?;
+                Ok::<_, TclError>(n * rest.parse::<i64>().unwrap())
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .eval("fact 10".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "3628800"
+        );
+    }
+
+    #[test]
+    fn test_createcommand_error() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("fail", |_, args| match args.len() {
+                0 => Err(TclError::new("wrong # args")),
+                _ => Ok(args[0].clone()),
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .eval("fail x".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "x"
+        );
+        assert_eq!(
+            interp.eval("fail".to_owned()).unwrap_err().message(),
+            "wrong # args"
+        );
+    }
+
+    #[test]
+    fn test_createcommand_error_options() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("relay", |interp, args| {
+                interp
+                    .eval(args[0].to_string())
+                    .and_then(Completion::into_result)
+            })
+            .unwrap();
+
+        let get = |interp: &mut TclInterp, code: &str| {
+            interp
+                .eval(code.to_owned())
+                .and_then(Completion::into_result)
+                .unwrap()
+        };
+
+        get(
+            &mut interp,
+            "catch {relay {error oops {} {MY CODE}}} msg opts",
+        );
+        assert_eq!(get(&mut interp, "set msg"), "oops");
+        assert_eq!(get(&mut interp, "dict get $opts -errorcode"), "MY CODE");
+
+        let error_info = get(&mut interp, "dict get $opts -errorinfo");
+        assert!(error_info.starts_with("oops\n    while executing\n\"error oops"));
+        assert!(error_info.contains("invoked from within\n\"relay"));
+        assert_eq!(error_info.matches("oops").count(), 3, "{}", error_info);
+
+        // Errors created in Rust have neither, even if a script failed before.
+        interp
+            .createcommand("fail", |interp, _| {
+                let _ = interp.eval("error inner {} {INNER CODE}".to_owned());
+                Err::<String, _>(TclError::new("outer"))
+            })
+            .unwrap();
+        get(&mut interp, "catch fail msg opts");
+        assert_eq!(get(&mut interp, "dict get $opts -errorcode"), "NONE");
+        assert!(!get(&mut interp, "dict get $opts -errorinfo").contains("inner"));
+    }
+
+    #[test]
+    fn test_createcommand_interp() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("double", |interp, args| {
+                interp.call_obj(&[
+                    "expr".to_tcl_obj(),
+                    args[0].clone(),
+                    "*".to_tcl_obj(),
+                    "2".to_tcl_obj(),
+                ])
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .eval("double 21".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "42"
+        );
+    }
+
+    #[test]
+    fn test_createcommand_drop() {
+        let mut interp = TclInterp::new().unwrap();
+        let dropped = Rc::new(Cell::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        interp
+            .createcommand("foo", move |_, _| {
+                let _ = &guard;
+                Ok::<_, TclError>("")
+            })
+            .unwrap();
+
+        assert!(!dropped.get());
+        interp
+            .eval("foo".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        interp
+            .eval("rename foo {}".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert!(dropped.get());
+    }
+
+    #[test]
+    fn test_createcommand_panic() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("boom", |_, _| -> Result<TclObj, TclError> {
+                panic!("kaboom")
+            })
+            .unwrap();
+
+        let err = interp.eval("boom".to_owned()).unwrap_err();
+        assert_eq!(err.message(), "Command \"boom\" panicked: kaboom");
+
+        // The command and the interpreter are still usable.
+        let err = interp
+            .eval("catch boom msg; set msg".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(err, "Command \"boom\" panicked: kaboom");
+    }
+
+    #[test]
+    fn test_createcommand_delete_self() {
+        let mut interp = TclInterp::new().unwrap();
+        let data = "still here".to_owned();
+        interp
+            .createcommand("once", move |interp, _| {
+                interp.deletecommand("once")?;
+                Ok::<_, TclError>(data.clone())
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .eval("once".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "still here"
+        );
+        assert!(interp.eval("once".to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_cmd_deleter_panic() {
+        struct PanicOnDrop;
+
+        impl Drop for PanicOnDrop {
+            fn drop(&mut self) {
+                panic!("drop");
+            }
+        }
+
+        let mut interp = TclInterp::new().unwrap();
+        let guard = PanicOnDrop;
+        interp
+            .createcommand("foo", move |_, _| {
+                let _ = &guard;
+                Ok::<_, TclError>("")
+            })
+            .unwrap();
+
+        interp.deletecommand("foo").unwrap();
+        assert!(interp.eval("foo".to_owned()).is_err());
+    }
+
     #[test]
     fn test_deletecommand() {
         let mut interp = TclInterp::new().unwrap();
         interp
-            .createcommand("foo", Box::new("bar"), |_, _| Ok("hi".to_tcl_obj()))
+            .createcommand("foo", |_, _| Ok::<_, TclError>("hi"))
             .unwrap();
 
-        assert!(attr!(interp.commands).contains_key(&CString::new("foo").unwrap()));
+        assert_eq!(attr!(interp.commands).len(), 1);
         assert!(interp.eval("foo".to_owned()).is_ok());
         interp.deletecommand("foo").unwrap();
-        assert!(!attr!(interp.commands).contains_key(&CString::new("foo").unwrap()));
+        assert!(attr!(interp.commands).is_empty());
         assert!(interp.eval("foo".to_owned()).is_err());
     }
+
+    #[test]
+    fn test_createcommand_nul() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("nul\0cmd", |_, args| {
+                let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+                Ok::<_, TclError>(args.join("|"))
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .call(&["nul\0cmd", "a\0b", "c"])
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "a\0b|c"
+        );
+        interp.deletecommand("nul\0cmd").unwrap();
+        assert!(interp.call(&["nul\0cmd"]).is_err());
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("foo", |_, _| Ok::<_, TclError>("hi"))
+            .unwrap();
+
+        interp.eval("rename foo bar".to_owned()).unwrap();
+        assert_eq!(
+            interp.eval("bar".to_owned()).unwrap(),
+            Completion::Ok("hi".to_owned())
+        );
+        assert!(interp.eval("foo".to_owned()).is_err());
+
+        // The old name is free again, but the new one is taken.
+        assert!(interp
+            .createcommand("bar", |_, _| Ok::<_, TclError>(""))
+            .is_err());
+        interp
+            .createcommand("foo", |_, _| Ok::<_, TclError>("new"))
+            .unwrap();
+        assert_eq!(attr!(interp.commands).len(), 2);
+
+        interp.deletecommand("bar").unwrap();
+        assert_eq!(attr!(interp.commands).len(), 1);
+        assert_eq!(
+            interp.eval("foo".to_owned()).unwrap(),
+            Completion::Ok("new".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_rename_error_message() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("foo", |_, _| -> Result<TclObj, TclError> { panic!("oops") })
+            .unwrap();
+
+        interp.eval("rename foo bar".to_owned()).unwrap();
+        let err = interp.eval("bar".to_owned()).unwrap_err();
+        assert_eq!(err.message(), "Command \"bar\" panicked: oops");
+    }
+
+    #[test]
+    fn test_delete_by_script() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("foo", |_, _| Ok::<_, TclError>("old"))
+            .unwrap();
+
+        interp.eval("rename foo {}".to_owned()).unwrap();
+        assert!(attr!(interp.commands).is_empty());
+        assert!(interp.deletecommand("foo").is_err());
+
+        interp
+            .createcommand("foo", |_, _| Ok::<_, TclError>("new"))
+            .unwrap();
+        assert_eq!(
+            interp.eval("foo".to_owned()).unwrap(),
+            Completion::Ok("new".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_recreate_in_namespace() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("::foo", |_, _| Ok::<_, TclError>("global"))
+            .unwrap();
+
+        // Both names refer to the same command.
+        assert!(interp
+            .createcommand("foo", |_, _| Ok::<_, TclError>(""))
+            .is_err());
+
+        interp.eval("namespace eval ns {}".to_owned()).unwrap();
+        interp.eval("rename foo ns::foo".to_owned()).unwrap();
+        interp
+            .createcommand("foo", |_, _| Ok::<_, TclError>("again"))
+            .unwrap();
+
+        assert_eq!(
+            interp.eval("list [foo] [ns::foo]".to_owned()).unwrap(),
+            Completion::Ok("again global".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_drop_interp() {
+        extern "C" fn set_deleted(client_data: *mut c_void, _interp: *mut tcl_sys::Tcl_Interp) {
+            unsafe { &*(client_data as *const Cell<bool>) }.set(true);
+        }
+
+        let interp_deleted = Box::new(Cell::new(false));
+        let closure_dropped = Rc::new(Cell::new(false));
+
+        let mut interp = TclInterp::new().unwrap();
+        unsafe {
+            tcl_sys::Tcl_CallWhenDeleted(
+                interp.interp_ptr().unwrap().as_ptr(),
+                Some(set_deleted),
+                &*interp_deleted as *const Cell<bool> as *mut c_void,
+            )
+        };
+
+        let guard = SetOnDrop(closure_dropped.clone());
+        interp
+            .createcommand("foo", move |interp, _| {
+                let _ = &guard;
+                interp.eval_obj("set x 1".to_owned())
+            })
+            .unwrap();
+        interp.eval("foo".to_owned()).unwrap();
+
+        // Commands don't hold on to the interpreter.
+        assert_eq!(Rc::strong_count(&interp.0), 1);
+
+        let other = interp.clone();
+        mem::drop(interp);
+        assert!(!interp_deleted.get());
+        assert!(!closure_dropped.get());
+
+        mem::drop(other);
+        assert!(interp_deleted.get());
+        assert!(closure_dropped.get());
+    }
+
+    #[test]
+    fn test_drop_deleted_interp() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("foo", |_, _| Ok::<_, TclError>(""))
+            .unwrap();
+
+        interp.delete().unwrap();
+        assert!(interp.deleted());
+        mem::drop(interp);
+    }
 }
@@ -0,0 +1,292 @@
+use std::{
+    cell::Cell,
+    ops::BitOr,
+    time::{Duration, Instant},
+};
+
+use super::*;
+
+/// Flags selecting which events `TclInterp::do_one_event` processes, and whether it may block.
+///
+/// Flags can be combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventFlags(c_int);
+
+impl EventFlags {
+    /// Return right away if there is no event to process, instead of waiting for one.
+    pub const DONT_WAIT: EventFlags = EventFlags(tcl_sys::TCL_DONT_WAIT as c_int);
+
+    /// Window system events, e.g. from Tk.
+    pub const WINDOW_EVENTS: EventFlags = EventFlags(tcl_sys::TCL_WINDOW_EVENTS as c_int);
+
+    /// Channels becoming readable or writable.
+    pub const FILE_EVENTS: EventFlags = EventFlags(tcl_sys::TCL_FILE_EVENTS as c_int);
+
+    /// Timers, as created by `after ms`.
+    pub const TIMER_EVENTS: EventFlags = EventFlags(tcl_sys::TCL_TIMER_EVENTS as c_int);
+
+    /// Idle callbacks, as created by `after idle`.
+    pub const IDLE_EVENTS: EventFlags = EventFlags(tcl_sys::TCL_IDLE_EVENTS as c_int);
+
+    /// Every kind of event. This is `TCL_ALL_EVENTS`, which is every flag but `DONT_WAIT`.
+    pub const ALL_EVENTS: EventFlags = EventFlags(!(tcl_sys::TCL_DONT_WAIT as c_int));
+
+    /// Use flags given as Tcl's raw `TCL_*_EVENTS` bits.
+    ///
+    /// Like in Tcl, flags without any kind of event mean `ALL_EVENTS`.
+    pub fn from_bits(bits: c_int) -> EventFlags {
+        EventFlags(bits)
+    }
+
+    pub fn bits(self) -> c_int {
+        self.0
+    }
+
+    pub fn contains(self, other: EventFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for EventFlags {
+    type Output = EventFlags;
+
+    fn bitor(self, other: EventFlags) -> EventFlags {
+        EventFlags(self.0 | other.0)
+    }
+}
+
+extern "C" fn deadline_callback(client_data: tcl_sys::ClientData) {
+    let reached = unsafe { &*(client_data as *const Cell<bool>) };
+    reached.set(true);
+}
+
+impl TclInterp {
+    /// Process a single event of one of the given kinds, waiting for one unless `DONT_WAIT` is
+    /// given.
+    ///
+    /// Returns whether an event was processed.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn do_one_event(&mut self, flags: EventFlags) -> Result<bool, TclError> {
+        // The event loop isn't tied to the interpreter, but event handlers usually are.
+        let _interp = self.interp_ptr()?;
+
+        Ok(unsafe { tcl_sys::Tcl_DoOneEvent(flags.bits()) } != 0)
+    }
+
+    /// Process every pending event without waiting, like the `update` command.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn update(&mut self) -> Result<(), TclError> {
+        while self.do_one_event(EventFlags::ALL_EVENTS | EventFlags::DONT_WAIT)? {}
+        Ok(())
+    }
+
+    /// Process pending window and idle events without waiting, like `update idletasks`.
+    ///
+    /// This brings the display up to date without running timers or handling user input.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn update_idletasks(&mut self) -> Result<(), TclError> {
+        while self.do_one_event(
+            EventFlags::WINDOW_EVENTS | EventFlags::IDLE_EVENTS | EventFlags::DONT_WAIT,
+        )? {}
+        Ok(())
+    }
+
+    /// Make the running `mainloop` or `run_until` return once the current event is handled.
+    pub fn quit(&mut self) {
+        debug!("Quitting mainloop");
+        attr!(self.quit_requested) = true;
+    }
+
+    /// Whether the event loop should stop, because of `quit`, because the main window was
+    /// destroyed or because the interpreter was deleted.
+    fn loop_done(&mut self) -> Result<bool, TclError> {
+        if self.deleted() || attr!(self.quit_requested) {
+            return Ok(true);
+        }
+
+        let exit_var_name = attr!(self.exit_var_name).clone();
+        Ok(self
+            .getvar(&exit_var_name, VarFlags::GLOBAL_ONLY)?
+            .to_string()
+            == "true")
+    }
+
+    /// Run the Tcl mainloop until `quit` is called, the main window is destroyed or the
+    /// interpreter is deleted.
+    ///
+    /// # Errors
+    /// This function fails if the exit variable can't be read.
+    pub fn mainloop(&mut self) -> Result<(), TclError> {
+        attr!(self.quit_requested) = false;
+
+        let res = (|| {
+            while !self.loop_done()? {
+                unsafe { tcl_sys::Tcl_DoOneEvent(EventFlags::ALL_EVENTS.0) };
+            }
+            Ok(())
+        })();
+
+        attr!(self.quit_requested) = false;
+        res
+    }
+
+    /// Run the Tcl mainloop like `mainloop`, but return once `deadline` has passed.
+    ///
+    /// Returns whether the loop stopped before the deadline, i.e. for one of the reasons
+    /// `mainloop` stops.
+    ///
+    /// # Errors
+    /// This function fails if the exit variable can't be read.
+    pub fn run_until(&mut self, deadline: Instant) -> Result<bool, TclError> {
+        attr!(self.quit_requested) = false;
+
+        let reached = Cell::new(false);
+        let now = Instant::now();
+        let remaining = if deadline > now {
+            deadline - now
+        } else {
+            Duration::from_secs(0)
+        };
+
+        // Round up so that we don't wake up just before the deadline and have to wait again.
+        let millis = (remaining + Duration::from_nanos(999_999)).as_millis();
+        let token = unsafe {
+            tcl_sys::Tcl_CreateTimerHandler(
+                millis.min(c_int::max_value() as u128) as c_int,
+                Some(deadline_callback),
+                &reached as *const Cell<bool> as tcl_sys::ClientData,
+            )
+        };
+
+        let res = (|| loop {
+            if self.loop_done()? {
+                return Ok(true);
+            }
+            if reached.get() || Instant::now() >= deadline {
+                return Ok(false);
+            }
+
+            unsafe { tcl_sys::Tcl_DoOneEvent(EventFlags::ALL_EVENTS.0) };
+        })();
+
+        // This does nothing if the timer already fired, but it must not fire after we're gone.
+        unsafe { tcl_sys::Tcl_DeleteTimerHandler(token) };
+        attr!(self.quit_requested) = false;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tclinterp::test_utils::getvar_string;
+
+    #[test]
+    fn test_do_one_event() {
+        let mut interp = TclInterp::new().unwrap();
+        interp.update().unwrap();
+
+        assert!(!interp.do_one_event(EventFlags::DONT_WAIT).unwrap());
+
+        interp.eval("after idle {set x 1}".to_owned()).unwrap();
+        assert!(!interp
+            .do_one_event(EventFlags::TIMER_EVENTS | EventFlags::DONT_WAIT)
+            .unwrap());
+        assert!(interp
+            .do_one_event(EventFlags::IDLE_EVENTS | EventFlags::DONT_WAIT)
+            .unwrap());
+        assert_eq!(getvar_string(&interp, "x"), "1");
+    }
+
+    #[test]
+    fn test_update() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .eval("set x 0; after 0 {incr x}; after idle {incr x 10}".to_owned())
+            .unwrap();
+
+        // Give the timer a chance to be due.
+        std::thread::sleep(Duration::from_millis(5));
+
+        interp.update_idletasks().unwrap();
+        assert_eq!(getvar_string(&interp, "x"), "10");
+
+        interp.update().unwrap();
+        assert_eq!(getvar_string(&interp, "x"), "11");
+    }
+
+    #[test]
+    fn test_run_until_deadline() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .eval("set x 0; after 10 {set x 1}; after 5000 {set x 2}".to_owned())
+            .unwrap();
+
+        let start = Instant::now();
+        assert!(!interp.run_until(start + Duration::from_millis(50)).unwrap());
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(start.elapsed() < Duration::from_millis(5000));
+        assert_eq!(getvar_string(&interp, "x"), "1");
+    }
+
+    #[test]
+    fn test_run_until_quit() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("quit", |interp, _| {
+                interp.quit();
+                Ok::<_, TclError>("")
+            })
+            .unwrap();
+        interp.eval("after 10 quit".to_owned()).unwrap();
+
+        let start = Instant::now();
+        assert!(interp.run_until(start + Duration::from_secs(5)).unwrap());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_mainloop_quit() {
+        let mut interp = TclInterp::new().unwrap();
+        interp
+            .createcommand("quit", |interp, _| {
+                interp.quit();
+                Ok::<_, TclError>("")
+            })
+            .unwrap();
+
+        interp
+            .eval("set n 0; after 10 {incr n; quit}".to_owned())
+            .unwrap();
+        interp.mainloop().unwrap();
+        assert_eq!(getvar_string(&interp, "n"), "1");
+
+        // Quitting only stops the loop that was running, so it can be entered again.
+        interp.eval("after 10 {incr n; quit}".to_owned()).unwrap();
+        interp.mainloop().unwrap();
+        assert_eq!(getvar_string(&interp, "n"), "2");
+    }
+
+    #[test]
+    fn test_deleted() {
+        let mut interp = TclInterp::new().unwrap();
+        interp.delete().unwrap();
+
+        assert_eq!(
+            interp
+                .do_one_event(EventFlags::DONT_WAIT)
+                .unwrap_err()
+                .kind(),
+            TclErrorKind::InterpDeleted
+        );
+        interp.mainloop().unwrap();
+    }
+}
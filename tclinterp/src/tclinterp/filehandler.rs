@@ -0,0 +1,331 @@
+use std::{
+    cell::RefCell,
+    ops::BitOr,
+    os::{raw::*, unix::io::RawFd},
+};
+
+use super::*;
+
+/// The conditions a file handler is called for.
+///
+/// Conditions can be combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMask(c_int);
+
+impl FileMask {
+    /// The file can be read from without blocking.
+    pub const READABLE: FileMask = FileMask(tcl_sys::TCL_READABLE as c_int);
+
+    /// The file can be written to without blocking.
+    pub const WRITABLE: FileMask = FileMask(tcl_sys::TCL_WRITABLE as c_int);
+
+    /// An exceptional condition, e.g. out-of-band data on a socket.
+    pub const EXCEPTION: FileMask = FileMask(tcl_sys::TCL_EXCEPTION as c_int);
+
+    const ALL: FileMask =
+        FileMask((tcl_sys::TCL_READABLE | tcl_sys::TCL_WRITABLE | tcl_sys::TCL_EXCEPTION) as c_int);
+
+    /// Use a mask given as Tcl's raw `TCL_READABLE`, `TCL_WRITABLE` and `TCL_EXCEPTION` bits.
+    pub fn from_bits(bits: c_int) -> FileMask {
+        FileMask(bits & Self::ALL.0)
+    }
+
+    pub fn bits(self) -> c_int {
+        self.0
+    }
+
+    pub fn contains(self, other: FileMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for FileMask {
+    type Output = FileMask;
+
+    fn bitor(self, other: FileMask) -> FileMask {
+        FileMask(self.0 | other.0)
+    }
+}
+
+/// The type-erased closure behind a handler created with `TclInterp::create_file_handler`.
+type FileCallback = dyn FnMut(&mut TclInterp, FileMask) -> Result<(), TclError>;
+
+struct FileHandlerData {
+    interp: WeakTclInterp,
+    callback: RefCell<Box<FileCallback>>,
+}
+
+thread_local! {
+    /// The file handlers of this thread, by file descriptor.
+    ///
+    /// Tcl keeps one handler per file descriptor for the whole thread, not per interpreter.
+    static FILE_HANDLERS: RefCell<HashMap<RawFd, Rc<FileHandlerData>>> = Default::default();
+}
+
+extern "C" fn file_proc(client_data: tcl_sys::ClientData, mask: c_int) {
+    let fd = client_data as usize as RawFd;
+
+    // The handler may delete itself while it runs, so keep the data alive until we're done.
+    let data = match FILE_HANDLERS.with(|handlers| handlers.borrow().get(&fd).cloned()) {
+        Some(data) => data,
+        None => return,
+    };
+
+    // File handlers are removed when their interpreter is dropped, but not when it's only
+    // deleted. Tcl would keep calling the handler then, so remove it.
+    let mut interp = match data.interp.upgrade() {
+        Some(ref interp) if !interp.deleted() => interp.clone(),
+        _ => {
+            debug!("Deleting file handler for fd {} of deleted interpreter", fd);
+
+            unsafe { tcl_sys::Tcl_DeleteFileHandler(fd) };
+            let _old = FILE_HANDLERS.with(|handlers| handlers.borrow_mut().remove(&fd));
+            return;
+        }
+    };
+    let what = format!("File handler for fd {}", fd);
+    trace!("Running {}", what);
+
+    let mut cb_interp = interp.clone();
+    let res = catch_panic(&what, || match data.callback.try_borrow_mut() {
+        Ok(mut callback) => callback(&mut cb_interp, FileMask::from_bits(mask)),
+        Err(_) => Err(TclError::new(format!("{} called itself recursively", what))),
+    });
+
+    if let Err(err) = res {
+        interp.background_error(err);
+    }
+}
+
+impl TclInterp {
+    /// Call `callback` whenever the file descriptor `fd` is ready for any of the conditions in
+    /// `mask`, like tkinter's `createfilehandler`.
+    ///
+    /// The callback only runs while the event loop is running. It receives the conditions that
+    /// are met, and if it fails or panics, the error is reported in the background, i.e. with
+    /// `bgerror`.
+    ///
+    /// There can only be one handler per file descriptor, even across interpreters, so this
+    /// replaces any existing handler for `fd`. The handler stays until it's deleted with
+    /// `delete_file_handler` or the interpreter is dropped.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn create_file_handler<F>(
+        &mut self,
+        fd: RawFd,
+        mask: FileMask,
+        callback: F,
+    ) -> Result<(), TclError>
+    where
+        F: FnMut(&mut TclInterp, FileMask) -> Result<(), TclError> + 'static,
+    {
+        self.interp_ptr()?;
+        debug!("Creating file handler for fd {} with mask {:?}", fd, mask);
+
+        let data = Rc::new(FileHandlerData {
+            interp: self.downgrade(),
+            callback: RefCell::new(Box::new(callback)),
+        });
+
+        // The old handler, if any, is dropped after we're done borrowing the registry, so that
+        // whatever its closure captured can use it too.
+        let _old = FILE_HANDLERS.with(|handlers| handlers.borrow_mut().insert(fd, data));
+
+        unsafe {
+            tcl_sys::Tcl_CreateFileHandler(
+                fd,
+                mask.bits(),
+                Some(file_proc),
+                fd as usize as tcl_sys::ClientData,
+            )
+        };
+
+        Ok(())
+    }
+
+    /// Delete the handler for the file descriptor `fd`, dropping its closure.
+    ///
+    /// This does nothing if there's no such handler.
+    pub fn delete_file_handler(&mut self, fd: RawFd) {
+        debug!("Deleting file handler for fd {}", fd);
+
+        let _old = FILE_HANDLERS.with(|handlers| handlers.borrow_mut().remove(&fd));
+        unsafe { tcl_sys::Tcl_DeleteFileHandler(fd) };
+    }
+}
+
+impl TclInterpData {
+    /// Delete the file handlers created through this interpreter.
+    pub(crate) fn remove_file_handlers(&mut self) {
+        let interp = self.interp;
+
+        // If the thread is exiting, the registry and the closures in it were dropped already.
+        let removed = FILE_HANDLERS.try_with(|handlers| {
+            let mut handlers = handlers.borrow_mut();
+            let fds = handlers
+                .iter()
+                .filter(|(_, data)| data.interp.ptr == interp)
+                .map(|(&fd, _)| fd)
+                .collect::<Vec<_>>();
+
+            fds.into_iter()
+                .map(|fd| {
+                    unsafe { tcl_sys::Tcl_DeleteFileHandler(fd) };
+                    handlers.remove(&fd)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // The closures are dropped here, after we're done borrowing the registry.
+        mem::drop(removed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        cell::Cell,
+        io::{Read, Write},
+        os::unix::{io::AsRawFd, net::UnixStream},
+        time::{Duration, Instant},
+    };
+
+    use crate::tclinterp::test_utils::{catch_bgerrors, getvar_string};
+
+    #[test]
+    fn test_readable() {
+        let mut interp = TclInterp::new().unwrap();
+        let (mut ours, mut theirs) = UnixStream::pair().unwrap();
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let fd = theirs.as_raw_fd();
+        {
+            let received = received.clone();
+            interp
+                .create_file_handler(fd, FileMask::READABLE, move |interp, mask| {
+                    assert_eq!(mask, FileMask::READABLE);
+
+                    let mut buf = [0; 16];
+                    let n = theirs.read(&mut buf).unwrap();
+                    received.borrow_mut().extend_from_slice(&buf[..n]);
+
+                    interp.quit();
+                    Ok(())
+                })
+                .unwrap();
+        }
+
+        interp.update().unwrap();
+        assert!(received.borrow().is_empty());
+
+        ours.write_all(b"hello").unwrap();
+        assert!(interp
+            .run_until(Instant::now() + Duration::from_secs(5))
+            .unwrap());
+        assert_eq!(&received.borrow()[..], b"hello");
+
+        interp.delete_file_handler(fd);
+        assert_eq!(Rc::strong_count(&received), 1);
+    }
+
+    #[test]
+    fn test_writable() {
+        let mut interp = TclInterp::new().unwrap();
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        let fd = ours.as_raw_fd();
+
+        let calls = Rc::new(Cell::new(0));
+        {
+            let calls = calls.clone();
+            interp
+                .create_file_handler(
+                    fd,
+                    FileMask::READABLE | FileMask::WRITABLE,
+                    move |interp, mask| {
+                        assert_eq!(mask, FileMask::WRITABLE);
+                        calls.set(calls.get() + 1);
+
+                        interp.delete_file_handler(fd);
+                        Ok(())
+                    },
+                )
+                .unwrap();
+        }
+
+        interp
+            .run_until(Instant::now() + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(Rc::strong_count(&calls), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interp = TclInterp::new().unwrap();
+        catch_bgerrors(&mut interp);
+
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        let fd = ours.as_raw_fd();
+        interp
+            .create_file_handler(fd, FileMask::WRITABLE, move |interp, _| {
+                interp.delete_file_handler(fd);
+                Err(TclError::new("failed"))
+            })
+            .unwrap();
+
+        interp
+            .run_until(Instant::now() + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(getvar_string(&interp, "errors"), "failed");
+    }
+
+    #[test]
+    fn test_dropped_interp() {
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        let data = Rc::new(());
+
+        {
+            let mut interp = TclInterp::new().unwrap();
+            let data = data.clone();
+            interp
+                .create_file_handler(ours.as_raw_fd(), FileMask::WRITABLE, move |_, _| {
+                    let _ = &data;
+                    panic!("handler of a dropped interpreter ran")
+                })
+                .unwrap();
+        }
+        assert_eq!(Rc::strong_count(&data), 1);
+
+        // Another interpreter runs the same event loop.
+        let mut interp = TclInterp::new().unwrap();
+        interp.update().unwrap();
+    }
+
+    #[test]
+    fn test_deleted_interp() {
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        let fd = ours.as_raw_fd();
+        let data = Rc::new(());
+
+        let mut deleted = TclInterp::new().unwrap();
+        {
+            let data = data.clone();
+            deleted
+                .create_file_handler(fd, FileMask::WRITABLE, move |_, _| {
+                    let _ = &data;
+                    panic!("handler of a deleted interpreter ran")
+                })
+                .unwrap();
+        }
+        deleted.delete().unwrap();
+
+        // The handler is removed the first time it would have run, instead of on every turn.
+        let mut interp = TclInterp::new().unwrap();
+        interp.update().unwrap();
+        assert_eq!(Rc::strong_count(&data), 1);
+        assert!(!FILE_HANDLERS.with(|handlers| handlers.borrow().contains_key(&fd)));
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+};
+
+use super::*;
+
+/// Something to run on the thread that owns an interpreter.
+///
+/// It's given `None` if the interpreter was dropped before the job got to run.
+type Job = Box<dyn FnOnce(Option<TclInterp>) + Send>;
+
+/// The event we queue on the owning thread. Tcl frees it with `Tcl_Free` once it's been handled,
+/// so it has to start with a `Tcl_Event` and be allocated with `Tcl_Alloc`.
+#[repr(C)]
+struct JobEvent {
+    header: tcl_sys::Tcl_Event,
+    interp_id: usize,
+    job_id: usize,
+}
+
+/// The jobs queued for a thread that haven't run yet, by id.
+///
+/// When a thread exits, Tcl frees the events still in its queue without handling them, so the
+/// jobs are kept here instead of in the events. They're dropped when the thread exits, which
+/// tells the threads waiting for them, and no more jobs are accepted after that.
+struct PendingJobs(Mutex<Option<HashMap<usize, Job>>>);
+
+impl PendingJobs {
+    fn take(&self, job_id: usize) -> Option<Job> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|jobs| jobs.remove(&job_id))
+    }
+
+    fn close(&self) {
+        let jobs = self.0.lock().unwrap().take();
+        if let Some(jobs) = jobs {
+            if !jobs.is_empty() {
+                debug!("Dropping {} jobs of exiting thread", jobs.len());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PendingJobs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("PendingJobs")
+    }
+}
+
+/// Closes the pending jobs of a thread when it exits.
+struct ThreadJobs(Arc<PendingJobs>);
+
+impl Drop for ThreadJobs {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+thread_local! {
+    /// The interpreters of this thread that have handles, by id.
+    static INTERPS: RefCell<HashMap<usize, Weak<Mutex<TclInterpData>>>> = Default::default();
+
+    /// The jobs other threads queued for this one.
+    static JOBS: ThreadJobs = ThreadJobs(Arc::new(PendingJobs(Mutex::new(Some(HashMap::new())))));
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+static NEXT_JOB_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Drop the jobs that were queued for this thread but didn't run, since Tcl is about to throw away
+/// their events.
+pub(crate) fn fail_pending_jobs() {
+    // This fails if the jobs are already gone, in which case they were closed then.
+    let _ = JOBS.try_with(|jobs| jobs.0.close());
+}
+
+/// The Tcl id of the thread that owns an interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ThreadId(tcl_sys::Tcl_ThreadId);
+
+// Tcl thread ids are opaque values that are meant to be passed around between threads.
+unsafe impl Send for ThreadId {}
+unsafe impl Sync for ThreadId {}
+
+impl ThreadId {
+    fn current() -> Self {
+        ThreadId(unsafe { tcl_sys::Tcl_GetCurrentThread() })
+    }
+}
+
+/// A handle to a `TclInterp` that can be sent to and shared between threads.
+///
+/// Tcl interpreters can only be used from the thread that created them, so the handle sends each
+/// request to that thread as a Tcl event and blocks until it has been handled. This means the
+/// owning thread has to be running the event loop (e.g. `TclInterp::mainloop`) for the requests
+/// to go through. Requests made from the owning thread itself run right away.
+#[derive(Debug, Clone)]
+pub struct TclInterpHandle {
+    thread: ThreadId,
+    interp_id: usize,
+    jobs: Arc<PendingJobs>,
+}
+
+extern "C" fn job_event_proc(ev: *mut tcl_sys::Tcl_Event, _flags: c_int) -> c_int {
+    let ev = unsafe { &mut *(ev as *mut JobEvent) };
+    let job = match JOBS.try_with(|jobs| jobs.0.take(ev.job_id)) {
+        Ok(Some(job)) => job,
+        _ => return 1,
+    };
+    let interp = TclInterpHandle::lookup(ev.interp_id);
+
+    trace!("Running job for interpreter {}", ev.interp_id);
+
+    // The job catches panics in the user's code itself, but it's better to be safe.
+    let res = catch_panic("Interpreter handle job", || {
+        job(interp);
+        Ok(())
+    });
+    if let Err(err) = res {
+        error!("{}", err);
+    }
+
+    // Tell Tcl the event was handled and can be freed.
+    1
+}
+
+impl TclInterpHandle {
+    fn lookup(interp_id: usize) -> Option<TclInterp> {
+        INTERPS.with(|interps| {
+            interps
+                .borrow()
+                .get(&interp_id)
+                .and_then(Weak::upgrade)
+                .map(TclInterp)
+        })
+    }
+
+    /// Run `f` with the interpreter on its owning thread and wait for the result.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was dropped, if its thread exited before running
+    /// `f`, or if `f` panics.
+    pub fn run<F, R>(&self, f: F) -> Result<R, TclError>
+    where
+        F: FnOnce(&mut TclInterp) -> Result<R, TclError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let job = move |interp: Option<TclInterp>| match interp {
+            Some(mut interp) => catch_panic("Interpreter handle job", || f(&mut interp)),
+            None => Err(TclError::with_kind(
+                TclErrorKind::InterpDeleted,
+                "Tried to use interpreter after it was dropped",
+            )),
+        };
+
+        if ThreadId::current() == self.thread {
+            return job(Self::lookup(self.interp_id));
+        }
+
+        let (tx, rx) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move |interp| {
+            // The requesting thread might have stopped caring, which is fine.
+            let _ = tx.send(job(interp));
+        });
+
+        debug!("Queueing job for interpreter {}", self.interp_id);
+
+        // The lock is held until the event is queued, so that the thread can't exit in between
+        // and leave the job behind.
+        let job_id = NEXT_JOB_ID.fetch_add(1, Ordering::SeqCst);
+        let mut jobs = self.jobs.0.lock().unwrap();
+        match jobs.as_mut() {
+            Some(jobs) => jobs.insert(job_id, job),
+            None => return Err(thread_exited()),
+        };
+
+        unsafe {
+            let ev = tcl_sys::Tcl_Alloc(mem::size_of::<JobEvent>() as c_uint) as *mut JobEvent;
+            ptr::write(
+                ev,
+                JobEvent {
+                    header: tcl_sys::Tcl_Event {
+                        proc_: Some(job_event_proc),
+                        nextPtr: ptr::null_mut(),
+                    },
+                    interp_id: self.interp_id,
+                    job_id,
+                },
+            );
+
+            tcl_sys::Tcl_ThreadQueueEvent(
+                self.thread.0,
+                ev as *mut tcl_sys::Tcl_Event,
+                tcl_sys::Tcl_QueuePosition_TCL_QUEUE_TAIL,
+            );
+            tcl_sys::Tcl_ThreadAlert(self.thread.0);
+        }
+        mem::drop(jobs);
+
+        rx.recv().unwrap_or_else(|_| Err(thread_exited()))
+    }
+
+    /// Evaluate a piece of Tcl code given as a string on the interpreter's thread.
+    ///
+    /// # Errors
+    /// This function fails for the same reasons as `TclInterp::eval` and `run`.
+    pub fn eval(&self, code: String) -> Result<Completion<String>, TclError> {
+        self.run(move |interp| interp.eval(code))
+    }
+
+    /// Evaluate a piece of Tcl code given as a list on the interpreter's thread.
+    ///
+    /// # Errors
+    /// This function fails for the same reasons as `TclInterp::call` and `run`.
+    pub fn call<I>(&self, it: I) -> Result<Completion<String>, TclError>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let args = it.into_iter().map(Into::into).collect::<Vec<String>>();
+        self.run(move |interp| interp.call(args))
+    }
+}
+
+fn thread_exited() -> TclError {
+    TclError::with_kind(
+        TclErrorKind::InterpDeleted,
+        "The interpreter's thread exited before handling the request",
+    )
+}
+
+impl TclInterp {
+    /// Get a handle to this interpreter that can be used from other threads.
+    pub fn handle(&self) -> TclInterpHandle {
+        let interp_id = *attr!(self.handle_id).get_or_insert_with(|| {
+            let interp_id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+            INTERPS.with(|interps| {
+                interps
+                    .borrow_mut()
+                    .insert(interp_id, Rc::downgrade(&self.0))
+            });
+            interp_id
+        });
+
+        TclInterpHandle {
+            thread: ThreadId::current(),
+            interp_id,
+            jobs: JOBS.with(|jobs| jobs.0.clone()),
+        }
+    }
+}
+
+impl TclInterpData {
+    /// Forget about the handles to this interpreter.
+    pub(crate) fn remove_handles(&mut self) {
+        if let Some(interp_id) = self.handle_id.take() {
+            // The registry of handles is gone if the thread is exiting, and this entry with it.
+            let _ = INTERPS.try_with(|interps| interps.borrow_mut().remove(&interp_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::{atomic::AtomicBool, Arc},
+        thread,
+        time::Duration,
+    };
+
+    /// Run the event loop of this thread until `f`, which runs on another thread, returns.
+    fn with_worker<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let done = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let done = done.clone();
+            thread::spawn(move || {
+                let res = f();
+                done.store(true, Ordering::SeqCst);
+                res
+            })
+        };
+
+        while !done.load(Ordering::SeqCst) {
+            unsafe { tcl_sys::Tcl_DoOneEvent(tcl_sys::TCL_DONT_WAIT as c_int) };
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        worker.join().unwrap()
+    }
+
+    #[test]
+    fn test_handle_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<TclInterpHandle>();
+    }
+
+    #[test]
+    fn test_handle_from_thread() {
+        let interp = TclInterp::new().unwrap();
+        let handle = interp.handle();
+
+        let res = with_worker(move || {
+            handle.eval("set x 21".to_owned()).unwrap();
+            handle.call(vec!["expr", "$x * 2"]).unwrap()
+        });
+        assert_eq!(res, Completion::Ok("42".to_owned()));
+        assert_eq!(
+            interp
+                .getvar("x", VarFlags::NONE)
+                .unwrap()
+                .extract::<i32>()
+                .unwrap(),
+            21
+        );
+    }
+
+    #[test]
+    fn test_handle_error() {
+        let interp = TclInterp::new().unwrap();
+        let handle = interp.handle();
+
+        let err = with_worker(move || handle.eval("error boom".to_owned()).unwrap_err());
+        assert_eq!(err.message(), "boom");
+        assert_eq!(err.kind(), TclErrorKind::Script);
+
+        let handle = interp.handle();
+        let err = with_worker(move || {
+            handle
+                .run(|_| -> Result<(), TclError> { panic!("in job") })
+                .unwrap_err()
+        });
+        assert_eq!(err.message(), "Interpreter handle job panicked: in job");
+    }
+
+    #[test]
+    fn test_handle_same_thread() {
+        let interp = TclInterp::new().unwrap();
+        let handle = interp.handle();
+
+        assert_eq!(
+            handle.eval("expr {1 + 1}".to_owned()).unwrap(),
+            Completion::Ok("2".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_handle_dropped_interp() {
+        let interp = TclInterp::new().unwrap();
+        let handle = interp.handle();
+        mem::drop(interp);
+
+        let err = with_worker(move || handle.eval("set x 1".to_owned()).unwrap_err());
+        assert_eq!(err.kind(), TclErrorKind::InterpDeleted);
+    }
+
+    #[test]
+    fn test_handle_thread_exited() {
+        let (handle_tx, handle_rx) = mpsc::channel();
+        let (exit_tx, exit_rx) = mpsc::channel::<()>();
+        let owner = thread::spawn(move || {
+            let interp = TclInterp::new().unwrap();
+            handle_tx.send(interp.handle()).unwrap();
+            // Exit without ever running the event loop.
+            let _ = exit_rx.recv();
+        });
+        let handle = handle_rx.recv().unwrap();
+
+        let waiting = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.eval("set x 1".to_owned()).unwrap_err())
+        };
+        thread::sleep(Duration::from_millis(100));
+        mem::drop(exit_tx);
+        owner.join().unwrap();
+
+        let err = waiting.join().unwrap();
+        assert_eq!(err.kind(), TclErrorKind::InterpDeleted);
+
+        let err = handle.eval("set x 1".to_owned()).unwrap_err();
+        assert_eq!(err.kind(), TclErrorKind::InterpDeleted);
+    }
+}
@@ -0,0 +1,368 @@
+use std::io::{self, Read, Write};
+
+use super::channel::new_channel;
+use super::*;
+
+/// A sink that passes everything written to it to a closure, for use with
+/// `TclInterp::capture_stdout` and `capture_stderr`.
+pub struct CallbackWriter<F>(F);
+
+impl<F: FnMut(&[u8])> CallbackWriter<F> {
+    pub fn new(callback: F) -> Self {
+        CallbackWriter(callback)
+    }
+}
+
+impl<F: FnMut(&[u8])> Write for CallbackWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "Unsupported channel operation")
+}
+
+/// Makes a `Write` usable as an output-only channel.
+struct WriteOnly<W>(W);
+
+impl<W> Read for WriteOnly<W> {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(unsupported())
+    }
+}
+
+impl<W: Write> Write for WriteOnly<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Makes a `Read` usable as an input-only channel.
+struct ReadOnly<R>(R);
+
+impl<R: Read> Read for ReadOnly<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R> Write for ReadOnly<R> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A standard channel replaced with `TclInterp::capture_stdout`, `capture_stderr` or
+/// `feed_stdin`.
+///
+/// The original channel is put back when this is dropped, which also closes the replacement and
+/// drops its sink or reader.
+pub struct StdRedirect {
+    interp: TclInterp,
+    type_: c_int,
+    original: tcl_sys::Tcl_Channel,
+    original_registered: bool,
+    channel: tcl_sys::Tcl_Channel,
+}
+
+impl StdRedirect {
+    /// Put the original channel back.
+    pub fn restore(self) {}
+}
+
+impl Drop for StdRedirect {
+    fn drop(&mut self) {
+        debug!("Restoring standard channel {}", self.type_);
+
+        // Releasing a standard channel closes it unless something else still holds on to it, so
+        // every reference we give up here belongs to a channel that isn't a standard one just
+        // then.
+        unsafe {
+            // Closing a standard channel from a script clears it, so the replacement is only
+            // still there if nobody did that.
+            let current = tcl_sys::Tcl_GetStdChannel(self.type_);
+            let interp = self.interp.interp_ptr().ok();
+
+            if !self.original.is_null() {
+                if let (Some(interp), true) = (&interp, self.original_registered) {
+                    tcl_sys::Tcl_RegisterChannel(interp.as_ptr(), self.original);
+                }
+                tcl_sys::Tcl_UnregisterChannel(ptr::null_mut(), self.original);
+            }
+            tcl_sys::Tcl_SetStdChannel(self.original, self.type_);
+
+            // This closes the replacement, unless the interpreter was deleted, which does that
+            // later on.
+            if current == self.channel {
+                if let Some(interp) = &interp {
+                    tcl_sys::Tcl_UnregisterChannel(interp.as_ptr(), self.channel);
+                }
+                tcl_sys::Tcl_UnregisterChannel(ptr::null_mut(), self.channel);
+            }
+        }
+    }
+}
+
+impl TclInterp {
+    fn redirect(
+        &mut self,
+        type_: u32,
+        stream: Box<dyn channel::Stream>,
+    ) -> Result<StdRedirect, TclError> {
+        let interp = self.interp_ptr()?;
+        let type_ = type_ as c_int;
+        debug!("Redirecting standard channel {}", type_);
+
+        unsafe {
+            // The interpreter registers the standard channels the first time it looks up a
+            // channel, which must not happen after we replaced them.
+            tcl_sys::Tcl_GetChannel(interp.as_ptr(), b"stdin\0".as_ptr() as _, ptr::null_mut());
+            tcl_sys::Tcl_ResetResult(interp.as_ptr());
+
+            // Keep the original open even if a script closes it in the meantime.
+            let original = tcl_sys::Tcl_GetStdChannel(type_);
+            if !original.is_null() {
+                tcl_sys::Tcl_RegisterChannel(ptr::null_mut(), original);
+            }
+
+            let mask = if type_ == tcl_sys::TCL_STDIN as c_int {
+                tcl_sys::TCL_READABLE
+            } else {
+                tcl_sys::TCL_WRITABLE
+            };
+            let channel = new_channel(stream, mask as c_int);
+
+            // Like the thread does for the real standard channels, we hold a reference besides
+            // the interpreter's, so that `close stdout` works the same.
+            tcl_sys::Tcl_RegisterChannel(ptr::null_mut(), channel);
+
+            // Whatever the script writes should show up right away, not when the buffer is full.
+            if mask == tcl_sys::TCL_WRITABLE {
+                tcl_sys::Tcl_SetChannelOption(
+                    ptr::null_mut(),
+                    channel,
+                    b"-buffering\0".as_ptr() as _,
+                    b"none\0".as_ptr() as _,
+                );
+            }
+
+            tcl_sys::Tcl_SetStdChannel(channel, type_);
+
+            // Commands remember which channel `stdout` was, until that channel is removed from the
+            // interpreter.
+            let original_registered = !original.is_null()
+                && tcl_sys::Tcl_IsChannelRegistered(interp.as_ptr(), original) != 0;
+            if original_registered {
+                tcl_sys::Tcl_UnregisterChannel(interp.as_ptr(), original);
+            }
+
+            // Scripts find the standard channels by looking up the name of the current one.
+            tcl_sys::Tcl_RegisterChannel(interp.as_ptr(), channel);
+
+            Ok(StdRedirect {
+                interp: self.clone(),
+                type_,
+                original,
+                original_registered,
+                channel,
+            })
+        }
+    }
+
+    /// Send everything scripts write to `stdout` to `sink` instead, until the returned guard is
+    /// dropped.
+    ///
+    /// Use a `CallbackWriter` to pass the output to a closure instead. The output is encoded
+    /// and translated like for the real `stdout`, and isn't buffered.
+    ///
+    /// The standard channels are shared by all interpreters on a thread, but only this one can
+    /// use the replacement, so the others have no `stdout` in the meantime. If the standard
+    /// channels are replaced several times, the guards must be dropped in reverse order.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn capture_stdout<W>(&mut self, sink: W) -> Result<StdRedirect, TclError>
+    where
+        W: Write + 'static,
+    {
+        self.redirect(tcl_sys::TCL_STDOUT, Box::new(WriteOnly(sink)))
+    }
+
+    /// Send everything scripts write to `stderr` to `sink` instead, until the returned guard is
+    /// dropped.
+    ///
+    /// This works like `capture_stdout`.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn capture_stderr<W>(&mut self, sink: W) -> Result<StdRedirect, TclError>
+    where
+        W: Write + 'static,
+    {
+        self.redirect(tcl_sys::TCL_STDERR, Box::new(WriteOnly(sink)))
+    }
+
+    /// Let scripts read from `source` when they read from `stdin`, until the returned guard is
+    /// dropped.
+    ///
+    /// Reading blocks if reading from `source` does, and returning 0 from `read` means end of
+    /// file. Otherwise this works like `capture_stdout`.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn feed_stdin<R>(&mut self, source: R) -> Result<StdRedirect, TclError>
+    where
+        R: Read + 'static,
+    {
+        self.redirect(tcl_sys::TCL_STDIN, Box::new(ReadOnly(source)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::RefCell, io::Cursor};
+
+    /// A sink that can still be looked at after it was moved into a channel.
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn eval(interp: &mut TclInterp, code: &str) -> Result<String, TclError> {
+        interp
+            .eval(code.to_owned())
+            .and_then(Completion::into_result)
+    }
+
+    #[test]
+    fn test_capture_stdout() {
+        let mut interp = TclInterp::new().unwrap();
+        let buffer = Buffer::default();
+
+        let redirect = interp.capture_stdout(buffer.clone()).unwrap();
+        eval(&mut interp, "puts hello; puts -nonewline world").unwrap();
+        assert_eq!(buffer.contents(), "hello\nworld");
+
+        redirect.restore();
+        assert_eq!(Rc::strong_count(&buffer.0), 1);
+
+        // The real stdout is back, which we don't want to clutter the test output with.
+        assert_ne!(
+            eval(&mut interp, "fconfigure stdout -buffering").unwrap(),
+            "none"
+        );
+    }
+
+    #[test]
+    fn test_capture_stderr() {
+        let mut interp = TclInterp::new().unwrap();
+        let lines = Rc::new(RefCell::new(Vec::<String>::new()));
+
+        let _redirect = {
+            let lines = lines.clone();
+            interp
+                .capture_stderr(CallbackWriter::new(move |buf: &[u8]| {
+                    lines
+                        .borrow_mut()
+                        .push(String::from_utf8_lossy(buf).into_owned())
+                }))
+                .unwrap()
+        };
+
+        eval(&mut interp, "puts stderr a; puts stderr b").unwrap();
+        assert_eq!(lines.borrow().concat(), "a\nb\n");
+    }
+
+    #[test]
+    fn test_feed_stdin() {
+        let mut interp = TclInterp::new().unwrap();
+        let _redirect = interp
+            .feed_stdin(Cursor::new(&b"first line\nsecond line\n"[..]))
+            .unwrap();
+
+        assert_eq!(eval(&mut interp, "gets stdin").unwrap(), "first line");
+        assert_eq!(eval(&mut interp, "read stdin").unwrap(), "second line\n");
+        assert_eq!(eval(&mut interp, "eof stdin").unwrap(), "1");
+        assert!(eval(&mut interp, "puts stdin foo").is_err());
+    }
+
+    #[test]
+    fn test_closed_by_script() {
+        let mut interp = TclInterp::new().unwrap();
+        let buffer = Buffer::default();
+
+        let redirect = interp.capture_stdout(buffer.clone()).unwrap();
+        eval(&mut interp, "puts hello; close stdout").unwrap();
+        assert_eq!(Rc::strong_count(&buffer.0), 1);
+        assert!(eval(&mut interp, "puts again").is_err());
+
+        redirect.restore();
+        eval(&mut interp, "fconfigure stdout").unwrap();
+        assert_eq!(buffer.contents(), "hello\n");
+    }
+
+    #[test]
+    fn test_nested() {
+        let mut interp = TclInterp::new().unwrap();
+        let (outer, inner) = (Buffer::default(), Buffer::default());
+
+        let outer_redirect = interp.capture_stdout(outer.clone()).unwrap();
+        eval(&mut interp, "puts 1").unwrap();
+
+        let inner_redirect = interp.capture_stdout(inner.clone()).unwrap();
+        eval(&mut interp, "puts 2").unwrap();
+        inner_redirect.restore();
+
+        eval(&mut interp, "puts 3").unwrap();
+        outer_redirect.restore();
+
+        assert_eq!(outer.contents(), "1\n3\n");
+        assert_eq!(inner.contents(), "2\n");
+    }
+
+    #[test]
+    fn test_deleted_interp() {
+        let mut interp = TclInterp::new().unwrap();
+        let buffer = Buffer::default();
+
+        let redirect = interp.capture_stdout(buffer.clone()).unwrap();
+        interp.delete().unwrap();
+        redirect.restore();
+        mem::drop(interp);
+        assert_eq!(Rc::strong_count(&buffer.0), 1);
+
+        let mut interp = TclInterp::new().unwrap();
+        eval(&mut interp, "fconfigure stdout").unwrap();
+    }
+}
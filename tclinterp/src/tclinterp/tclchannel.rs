@@ -0,0 +1,381 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::*;
+
+/// How a channel buffers output, as set by `fconfigure -buffering`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buffering {
+    /// Only write when the buffer is full or the channel is flushed.
+    Full,
+
+    /// Also write whenever a newline is written.
+    Line,
+
+    /// Write everything right away.
+    None,
+}
+
+/// How a channel translates line endings, as set by `fconfigure -translation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Translation {
+    /// Accept any line ending on input and use the platform's own on output.
+    Auto,
+
+    /// No translation, and no encoding either.
+    Binary,
+
+    /// Carriage returns.
+    Cr,
+
+    /// Carriage returns followed by line feeds.
+    Crlf,
+
+    /// Line feeds.
+    Lf,
+}
+
+/// An existing Tcl channel, such as `stdout` or the result of `open`, used from Rust.
+///
+/// Reading and writing go through `Tcl_ReadRaw` and `Tcl_WriteRaw`, so they see the bytes as they
+/// are in the channel, without any of the encoding or translation `gets` and `puts` do.
+///
+/// The channel stays open for as long as this exists, even if a script closes it. In that case it
+/// is really closed once this is dropped. It doesn't keep the interpreter alive though, so options
+/// can't be set anymore once that was dropped.
+pub struct TclChannel {
+    interp: WeakTclInterp,
+    name: String,
+    channel: tcl_sys::Tcl_Channel,
+}
+
+impl Drop for TclChannel {
+    fn drop(&mut self) {
+        debug!("Releasing channel {:?}", self.name);
+
+        // This closes the channel if nobody else is using it.
+        unsafe { tcl_sys::Tcl_UnregisterChannel(ptr::null_mut(), self.channel) };
+    }
+}
+
+/// Build an I/O error from the errno a Tcl channel function left behind.
+fn last_os_error() -> io::Error {
+    io::Error::from_raw_os_error(unsafe { tcl_sys::Tcl_GetErrno() })
+}
+
+impl TclChannel {
+    /// Look up the channel called `name` in `interp`.
+    ///
+    /// # Errors
+    /// This function fails if there's no such channel.
+    pub fn new(interp: TclInterp, name: &str) -> Result<Self, TclError> {
+        let c_name = mutf8::to_cstring(name);
+
+        let channel = unsafe {
+            tcl_sys::Tcl_GetChannel(
+                interp.interp_ptr()?.as_ptr(),
+                c_name.as_ptr(),
+                ptr::null_mut(),
+            )
+        };
+        if channel.is_null() {
+            return Err(interp.get_error()?);
+        }
+
+        // This keeps the channel alive without tying it to any interpreter.
+        unsafe { tcl_sys::Tcl_RegisterChannel(ptr::null_mut(), channel) };
+
+        Ok(Self {
+            interp: interp.downgrade(),
+            name: name.to_owned(),
+            channel,
+        })
+    }
+
+    /// The name scripts know the channel by.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set any of the channel's `fconfigure` options, e.g. `-eofchar`.
+    ///
+    /// # Errors
+    /// This function fails if the option does not exist or the value is invalid for it.
+    pub fn set_option(&mut self, option: &str, value: &str) -> Result<(), TclError> {
+        trace!(
+            "Setting option {} of channel {:?} to {:?}",
+            option,
+            self.name,
+            value
+        );
+
+        let option = mutf8::to_cstring(option);
+        let value = mutf8::to_cstring(value);
+
+        let interp = self.interp.upgrade().ok_or_else(|| {
+            TclError::with_kind(
+                TclErrorKind::InterpDeleted,
+                "Tried to use interpreter after it was dropped",
+            )
+        })?;
+        let interp_ptr = interp.interp_ptr()?;
+        interp.check_statuscode(unsafe {
+            tcl_sys::Tcl_SetChannelOption(
+                interp_ptr.as_ptr(),
+                self.channel,
+                option.as_ptr(),
+                value.as_ptr(),
+            )
+        })
+    }
+
+    /// Set whether reads and writes wait until they can be done (`-blocking`).
+    ///
+    /// # Errors
+    /// This function fails if the channel's driver fails to change its mode.
+    pub fn set_blocking(&mut self, blocking: bool) -> Result<(), TclError> {
+        self.set_option("-blocking", if blocking { "1" } else { "0" })
+    }
+
+    /// Set how output is buffered (`-buffering`).
+    ///
+    /// # Errors
+    /// This function fails for the same reasons as `set_option`.
+    pub fn set_buffering(&mut self, buffering: Buffering) -> Result<(), TclError> {
+        let value = match buffering {
+            Buffering::Full => "full",
+            Buffering::Line => "line",
+            Buffering::None => "none",
+        };
+        self.set_option("-buffering", value)
+    }
+
+    /// Set the size of the channel's buffers in bytes (`-buffersize`).
+    ///
+    /// # Errors
+    /// This function fails for the same reasons as `set_option`.
+    pub fn set_buffer_size(&mut self, size: usize) -> Result<(), TclError> {
+        self.set_option("-buffersize", &size.to_string())
+    }
+
+    /// Set the encoding used by `gets`, `read` and `puts` (`-encoding`).
+    ///
+    /// # Errors
+    /// This function fails if there is no such encoding.
+    pub fn set_encoding(&mut self, encoding: &str) -> Result<(), TclError> {
+        self.set_option("-encoding", encoding)
+    }
+
+    /// Set how line endings are translated for both input and output (`-translation`).
+    ///
+    /// # Errors
+    /// This function fails for the same reasons as `set_option`.
+    pub fn set_translation(&mut self, translation: Translation) -> Result<(), TclError> {
+        let value = match translation {
+            Translation::Auto => "auto",
+            Translation::Binary => "binary",
+            Translation::Cr => "cr",
+            Translation::Crlf => "crlf",
+            Translation::Lf => "lf",
+        };
+        self.set_option("-translation", value)
+    }
+}
+
+impl Read for TclChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::max_value() as usize) as c_int;
+        let n = unsafe { tcl_sys::Tcl_ReadRaw(self.channel, buf.as_mut_ptr() as *mut c_char, len) };
+
+        if n < 0 {
+            Err(last_os_error())
+        } else if n == 0 && unsafe { tcl_sys::Tcl_InputBlocked(self.channel) } != 0 {
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+impl Write for TclChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::max_value() as usize) as c_int;
+        let n = unsafe { tcl_sys::Tcl_WriteRaw(self.channel, buf.as_ptr() as *const c_char, len) };
+
+        if n < 0 {
+            Err(last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match unsafe { tcl_sys::Tcl_Flush(self.channel) } as c_uint {
+            tcl_sys::TCL_OK => Ok(()),
+            _ => Err(last_os_error()),
+        }
+    }
+}
+
+impl Seek for TclChannel {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (offset, mode) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, libc::SEEK_SET),
+            SeekFrom::Current(offset) => (offset, libc::SEEK_CUR),
+            SeekFrom::End(offset) => (offset, libc::SEEK_END),
+        };
+
+        let pos = unsafe { tcl_sys::Tcl_Seek(self.channel, offset as tcl_sys::Tcl_WideInt, mode) };
+        if pos < 0 {
+            Err(last_os_error())
+        } else {
+            Ok(pos as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a pipe with `chan pipe`, returning the names of its read and write ends.
+    fn pipe(interp: &mut TclInterp) -> (String, String) {
+        let ends = interp
+            .eval("chan pipe".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        let ends = interp.splitlist(ends.as_str()).unwrap();
+        (ends[0].clone(), ends[1].clone())
+    }
+
+    #[test]
+    fn test_pipe() {
+        let mut interp = TclInterp::new().unwrap();
+        let (read_end, write_end) = pipe(&mut interp);
+
+        let mut reader = TclChannel::new(interp.clone(), &read_end).unwrap();
+        let mut writer = TclChannel::new(interp.clone(), &write_end).unwrap();
+        assert_eq!(reader.name(), read_end);
+
+        let data = (0..=255).collect::<Vec<u8>>();
+        writer.write_all(&data).unwrap();
+        writer.flush().unwrap();
+
+        let mut received = vec![0; data.len()];
+        reader.read_exact(&mut received).unwrap();
+        assert_eq!(received, data);
+
+        // Scripts see the same channel.
+        writer.write_all(b"from rust\n").unwrap();
+        let line = interp.call(&["gets", &read_end]).unwrap();
+        assert_eq!(line, Completion::Ok("from rust".to_owned()));
+
+        reader.set_blocking(false).unwrap();
+        let err = reader.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+        mem::drop(writer);
+        interp.call(&["close", &write_end]).unwrap();
+        reader.set_blocking(true).unwrap();
+        assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut interp = TclInterp::new().unwrap();
+        let name = interp
+            .eval("file tempfile".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        let mut file = TclChannel::new(interp.clone(), &name).unwrap();
+
+        file.write_all(b"hello, world").unwrap();
+        assert_eq!(file.seek(SeekFrom::Start(7)).unwrap(), 7);
+
+        let mut data = String::new();
+        file.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "world");
+
+        assert_eq!(file.seek(SeekFrom::End(-5)).unwrap(), 7);
+        assert_eq!(file.seek(SeekFrom::Current(-2)).unwrap(), 5);
+        assert_eq!(
+            file.seek(SeekFrom::Current(-10)).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        interp.call(&["close", &name]).unwrap();
+    }
+
+    #[test]
+    fn test_options() {
+        let mut interp = TclInterp::new().unwrap();
+        let (read_end, write_end) = pipe(&mut interp);
+        let mut writer = TclChannel::new(interp.clone(), &write_end).unwrap();
+
+        let get = |interp: &mut TclInterp, option: &str| {
+            interp
+                .call(&["fconfigure", &write_end, option])
+                .and_then(Completion::into_result)
+                .unwrap()
+        };
+
+        writer.set_buffering(Buffering::Line).unwrap();
+        assert_eq!(get(&mut interp, "-buffering"), "line");
+        writer.set_buffer_size(1234).unwrap();
+        assert_eq!(get(&mut interp, "-buffersize"), "1234");
+        writer.set_encoding("utf-8").unwrap();
+        assert_eq!(get(&mut interp, "-encoding"), "utf-8");
+        writer.set_translation(Translation::Crlf).unwrap();
+        assert_eq!(get(&mut interp, "-translation"), "crlf");
+        writer.set_blocking(false).unwrap();
+        assert_eq!(get(&mut interp, "-blocking"), "0");
+
+        // `puts` uses the options we set.
+        interp.call(&["puts", &write_end, "line"]).unwrap();
+        let mut reader = TclChannel::new(interp.clone(), &read_end).unwrap();
+        let mut received = [0; 6];
+        reader.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"line\r\n");
+
+        let err = writer.set_encoding("no-such-encoding").unwrap_err();
+        assert_eq!(err.message(), "unknown encoding \"no-such-encoding\"");
+        assert!(writer.set_option("-nonsense", "1").is_err());
+        let err = writer.set_encoding("no\0such").unwrap_err();
+        assert_eq!(err.message(), "unknown encoding \"no\0such\"");
+    }
+
+    #[test]
+    fn test_closed_by_script() {
+        let mut interp = TclInterp::new().unwrap();
+        let (read_end, write_end) = pipe(&mut interp);
+        let mut writer = TclChannel::new(interp.clone(), &write_end).unwrap();
+
+        // The script can't use the channel anymore, but we still can.
+        interp.call(&["close", &write_end]).unwrap();
+        assert!(interp.call(&["puts", &write_end, "x"]).is_err());
+        writer.write_all(b"still open\n").unwrap();
+
+        let line = interp.call(&["gets", &read_end]).unwrap();
+        assert_eq!(line, Completion::Ok("still open".to_owned()));
+
+        // Dropping our reference really closes it.
+        mem::drop(writer);
+        let line = interp.call(&["gets", &read_end]).unwrap();
+        assert_eq!(line, Completion::Ok("".to_owned()));
+        assert_eq!(
+            interp.call(&["eof", &read_end]).unwrap(),
+            Completion::Ok("1".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_no_such_channel() {
+        let interp = TclInterp::new().unwrap();
+        let err = TclChannel::new(interp, "nope").err().unwrap();
+        assert_eq!(err.message(), "can not find channel named \"nope\"");
+
+        // Like everywhere else, a NUL is just another character.
+        let interp = TclInterp::new().unwrap();
+        let err = TclChannel::new(interp, "no\0pe").err().unwrap();
+        assert_eq!(err.message(), "can not find channel named \"no\0pe\"");
+    }
+}
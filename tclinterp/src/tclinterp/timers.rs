@@ -0,0 +1,327 @@
+use std::{
+    cell::{Cell, RefCell},
+    os::raw::*,
+    time::Duration,
+};
+
+use super::*;
+
+/// The type-erased closure behind a callback created with `TclInterp::after` or `after_idle`.
+type Callback = dyn FnOnce(&mut TclInterp) -> Result<(), TclError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CallbackKind {
+    Timer,
+    Idle,
+}
+
+struct CallbackData {
+    interp: WeakTclInterp,
+    kind: CallbackKind,
+    timer: Cell<tcl_sys::Tcl_TimerToken>,
+
+    /// Taken out when the callback runs, which also marks it as no longer pending.
+    callback: RefCell<Option<Box<Callback>>>,
+}
+
+/// A token for a callback created with `TclInterp::after` or `after_idle`, which can be used to
+/// cancel it.
+///
+/// Dropping the token does not cancel the callback.
+pub struct TimerToken {
+    data: Weak<CallbackData>,
+    ptr: *const CallbackData,
+}
+
+impl TimerToken {
+    /// Whether the callback has neither run nor been cancelled yet.
+    pub fn pending(&self) -> bool {
+        match self.data.upgrade() {
+            Some(data) => data.callback.borrow().is_some(),
+            None => false,
+        }
+    }
+
+    /// Cancel the callback, dropping the closure and everything it captured.
+    ///
+    /// Returns whether the callback was still pending.
+    pub fn cancel(self) -> bool {
+        if !self.pending() {
+            return false;
+        }
+
+        debug!("Cancelling callback");
+
+        // This takes back the reference we gave to Tcl.
+        let data = unsafe { Rc::from_raw(self.ptr) };
+        unsafe {
+            match data.kind {
+                CallbackKind::Timer => tcl_sys::Tcl_DeleteTimerHandler(data.timer.get()),
+                CallbackKind::Idle => tcl_sys::Tcl_CancelIdleCall(
+                    Some(callback_proc),
+                    self.ptr as tcl_sys::ClientData,
+                ),
+            }
+        }
+
+        true
+    }
+}
+
+extern "C" fn callback_proc(client_data: tcl_sys::ClientData) {
+    // Tcl only calls us once, so this takes back the reference we gave to it.
+    let data = unsafe { Rc::from_raw(client_data as *const CallbackData) };
+    let what = match data.kind {
+        CallbackKind::Timer => "Timer callback",
+        CallbackKind::Idle => "Idle callback",
+    };
+
+    let callback = match data.callback.borrow_mut().take() {
+        Some(callback) => callback,
+        None => return,
+    };
+
+    // Timers belong to the thread rather than to the interpreter, so it may be gone by now.
+    let mut interp = match data.interp.upgrade() {
+        Some(ref interp) if !interp.deleted() => interp.clone(),
+        _ => {
+            debug!("Dropping {} of a dropped interpreter", what);
+            return;
+        }
+    };
+    trace!("Running {}", what);
+
+    let mut cb_interp = interp.clone();
+    let res = catch_panic(what, move || callback(&mut cb_interp));
+
+    if let Err(err) = res {
+        interp.background_error(err);
+    }
+}
+
+impl TclInterp {
+    fn schedule<F>(&mut self, kind: CallbackKind, callback: F) -> Result<TimerToken, TclError>
+    where
+        F: FnOnce(&mut TclInterp) -> Result<(), TclError> + 'static,
+    {
+        self.interp_ptr()?;
+
+        let data = Rc::new(CallbackData {
+            interp: self.downgrade(),
+            kind,
+            timer: Cell::new(ptr::null_mut()),
+            callback: RefCell::new(Some(Box::new(callback))),
+        });
+        let token = TimerToken {
+            data: Rc::downgrade(&data),
+            ptr: Rc::into_raw(data),
+        };
+
+        Ok(token)
+    }
+
+    /// Run `callback` once after `delay` has passed, like the `after ms script` command.
+    ///
+    /// The callback only runs while the event loop is running, and not at all if the interpreter
+    /// was dropped before that. If it fails or panics, the error is reported in the background
+    /// like an error in an `after` script, i.e. with `bgerror`.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn after<F>(&mut self, delay: Duration, callback: F) -> Result<TimerToken, TclError>
+    where
+        F: FnOnce(&mut TclInterp) -> Result<(), TclError> + 'static,
+    {
+        let token = self.schedule(CallbackKind::Timer, callback)?;
+
+        let millis = delay.as_millis().min(c_int::max_value() as u128) as c_int;
+        debug!("Creating timer for {}ms", millis);
+
+        let timer = unsafe {
+            tcl_sys::Tcl_CreateTimerHandler(
+                millis,
+                Some(callback_proc),
+                token.ptr as tcl_sys::ClientData,
+            )
+        };
+        unsafe { &*token.ptr }.timer.set(timer);
+
+        Ok(token)
+    }
+
+    /// Run `callback` once the event loop is idle, like the `after idle script` command.
+    ///
+    /// This behaves like `after` otherwise.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn after_idle<F>(&mut self, callback: F) -> Result<TimerToken, TclError>
+    where
+        F: FnOnce(&mut TclInterp) -> Result<(), TclError> + 'static,
+    {
+        let token = self.schedule(CallbackKind::Idle, callback)?;
+
+        debug!("Creating idle callback");
+        unsafe { tcl_sys::Tcl_DoWhenIdle(Some(callback_proc), token.ptr as tcl_sys::ClientData) };
+
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    use crate::tclinterp::test_utils::{catch_bgerrors, getvar_string};
+
+    fn getvar_i32(interp: &TclInterp, name: &str) -> i32 {
+        interp
+            .getvar(name, VarFlags::NONE)
+            .unwrap()
+            .extract::<i32>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_after() {
+        let mut interp = TclInterp::new().unwrap();
+        interp.setvar("x", "0", VarFlags::NONE).unwrap();
+
+        let start = Instant::now();
+        let token = interp
+            .after(Duration::from_millis(20), move |interp| {
+                interp.setvar("x", start.elapsed().as_millis() as i32, VarFlags::NONE)?;
+                Ok(())
+            })
+            .unwrap();
+        assert!(token.pending());
+
+        interp.update().unwrap();
+        assert_eq!(getvar_string(&interp, "x"), "0");
+
+        interp
+            .run_until(start + Duration::from_millis(100))
+            .unwrap();
+        assert!(!token.pending());
+        assert!(getvar_i32(&interp, "x") >= 20);
+        assert!(!token.cancel());
+    }
+
+    #[test]
+    fn test_after_idle() {
+        let mut interp = TclInterp::new().unwrap();
+        interp.setvar("order", "", VarFlags::NONE).unwrap();
+
+        interp
+            .after_idle(|interp| {
+                interp.eval("lappend order idle".to_owned())?;
+                Ok(())
+            })
+            .unwrap();
+        interp.eval("lappend order now".to_owned()).unwrap();
+
+        interp.update_idletasks().unwrap();
+        assert_eq!(getvar_string(&interp, "order"), "now idle");
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut interp = TclInterp::new().unwrap();
+        let data = Rc::new(());
+
+        let timer = {
+            let data = data.clone();
+            interp
+                .after(Duration::from_millis(0), move |_| {
+                    let _ = &data;
+                    panic!("cancelled timer ran")
+                })
+                .unwrap()
+        };
+        let idle = {
+            let data = data.clone();
+            interp
+                .after_idle(move |_| {
+                    let _ = &data;
+                    panic!("cancelled idle callback ran")
+                })
+                .unwrap()
+        };
+        assert_eq!(Rc::strong_count(&data), 3);
+
+        assert!(timer.cancel());
+        assert!(idle.cancel());
+        assert_eq!(Rc::strong_count(&data), 1);
+
+        catch_bgerrors(&mut interp);
+        std::thread::sleep(Duration::from_millis(5));
+        interp.update().unwrap();
+        assert_eq!(getvar_string(&interp, "errors"), "");
+    }
+
+    #[test]
+    fn test_cancel_from_callback() {
+        let mut interp = TclInterp::new().unwrap();
+
+        let token = Rc::new(RefCell::new(None));
+        *token.borrow_mut() = Some(
+            interp
+                .after_idle({
+                    let token = token.clone();
+                    move |_| {
+                        let token: Option<TimerToken> = token.borrow_mut().take();
+                        assert!(!token.unwrap().cancel());
+                        Ok(())
+                    }
+                })
+                .unwrap(),
+        );
+
+        catch_bgerrors(&mut interp);
+        interp.update().unwrap();
+        assert_eq!(getvar_string(&interp, "errors"), "");
+        assert!(token.borrow().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interp = TclInterp::new().unwrap();
+        catch_bgerrors(&mut interp);
+
+        interp.after_idle(|_| Err(TclError::new("failed"))).unwrap();
+        interp
+            .after_idle(|_| -> Result<(), TclError> { panic!("boom") })
+            .unwrap();
+
+        interp.update().unwrap();
+        assert_eq!(
+            getvar_string(&interp, "errors"),
+            "failed {Idle callback panicked: boom}"
+        );
+    }
+
+    #[test]
+    fn test_dropped_interp() {
+        let data = Rc::new(());
+
+        let token = {
+            let mut interp = TclInterp::new().unwrap();
+            let data = data.clone();
+            interp
+                .after_idle(move |_| {
+                    let _ = &data;
+                    panic!("callback of a dropped interpreter ran")
+                })
+                .unwrap()
+        };
+        assert!(token.pending());
+
+        // Another interpreter runs the same event loop.
+        let mut interp = TclInterp::new().unwrap();
+        interp.update().unwrap();
+        assert!(!token.pending());
+        assert_eq!(Rc::strong_count(&data), 1);
+    }
+}
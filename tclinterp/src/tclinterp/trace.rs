@@ -0,0 +1,447 @@
+use std::{
+    cell::{Cell, RefCell},
+    ops::BitOr,
+};
+
+use super::*;
+
+/// The variable operations a trace can be notified of.
+///
+/// Operations can be combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceOps(c_int);
+
+impl TraceOps {
+    /// The variable is being read.
+    pub const READ: TraceOps = TraceOps(tcl_sys::TCL_TRACE_READS as c_int);
+
+    /// The variable has just been written.
+    pub const WRITE: TraceOps = TraceOps(tcl_sys::TCL_TRACE_WRITES as c_int);
+
+    /// The variable has been unset, either explicitly or because the interpreter was deleted.
+    pub const UNSET: TraceOps = TraceOps(tcl_sys::TCL_TRACE_UNSETS as c_int);
+
+    /// The variable is an array and it is being accessed through the `array` command.
+    pub const ARRAY: TraceOps = TraceOps(tcl_sys::TCL_TRACE_ARRAY as c_int);
+
+    const ALL: TraceOps = TraceOps(
+        (tcl_sys::TCL_TRACE_READS
+            | tcl_sys::TCL_TRACE_WRITES
+            | tcl_sys::TCL_TRACE_UNSETS
+            | tcl_sys::TCL_TRACE_ARRAY) as c_int,
+    );
+
+    pub fn contains(self, other: TraceOps) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn intersects(self, other: TraceOps) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    fn from_flags(flags: c_int) -> TraceOps {
+        TraceOps(flags & Self::ALL.0)
+    }
+}
+
+impl BitOr for TraceOps {
+    type Output = TraceOps;
+
+    fn bitor(self, other: TraceOps) -> TraceOps {
+        TraceOps(self.0 | other.0)
+    }
+}
+
+/// The callback of a variable trace.
+///
+/// It receives the variable name, the array element (if any) and the operation that triggered
+/// it. An error returned from a read or write trace makes the operation fail with its message.
+pub type TraceCallback =
+    dyn FnMut(&mut TclInterp, &str, Option<&str>, TraceOps) -> Result<(), TclError>;
+
+struct TraceData {
+    // The guard may end up in something the interpreter owns, e.g. a command, so a strong
+    // reference here could keep the interpreter alive forever.
+    interp: WeakTclInterp,
+    ops: TraceOps,
+    callback: RefCell<Box<TraceCallback>>,
+
+    /// Set once Tcl has removed the trace on its own, which happens when the variable is unset
+    /// and when the interpreter is deleted.
+    destroyed: Cell<bool>,
+}
+
+/// A guard for a variable trace created by `TclInterp::trace_var`.
+///
+/// The trace is removed when the guard is dropped.
+pub struct VarTrace {
+    name: CString,
+    flags: c_int,
+    data: Rc<TraceData>,
+
+    /// The reference we gave to Tcl, which it holds until the trace is removed.
+    ptr: *const TraceData,
+}
+
+extern "C" fn trace_callback(
+    client_data: *mut c_void,
+    _interp: *mut tcl_sys::Tcl_Interp,
+    name1: *const c_char,
+    name2: *const c_char,
+    flags: c_int,
+) -> *mut c_char {
+    // The guard may be dropped while the callback runs, so keep the data alive until we're done.
+    let data = unsafe {
+        let tcl_ref = Rc::from_raw(client_data as *const TraceData);
+        let our_ref = tcl_ref.clone();
+        mem::forget(tcl_ref);
+        our_ref
+    };
+
+    if flags & tcl_sys::TCL_TRACE_DESTROYED as c_int != 0 {
+        debug!("Trace on {:?} destroyed by Tcl", unsafe {
+            CStr::from_ptr(name1)
+        });
+        data.destroyed.set(true);
+
+        // Tcl won't call us again, so this takes back the reference we gave to it.
+        mem::drop(unsafe { Rc::from_raw(client_data as *const TraceData) });
+    }
+
+    // We always ask for unsets to know when the trace goes away, but the user may not have.
+    let op = TraceOps::from_flags(flags);
+    if !data.ops.intersects(op) {
+        return ptr::null_mut();
+    }
+
+    // The last unsets happen while the interpreter is being dropped, when nobody can use it.
+    let mut interp = match data.interp.upgrade() {
+        Some(interp) => interp,
+        None => return ptr::null_mut(),
+    };
+
+    let res = catch_panic("Variable trace", || {
+        let name1 = mutf8::decode(unsafe { CStr::from_ptr(name1) }.to_bytes());
+        let name2 = if name2.is_null() {
+            None
+        } else {
+            Some(mutf8::decode(unsafe { CStr::from_ptr(name2) }.to_bytes()))
+        };
+        trace!("Calling trace on {:?} {:?} for {:?}", name1, name2, op);
+
+        // Tcl disables traces on a variable while one of them is running, so this should never
+        // fail.
+        let mut callback = match data.callback.try_borrow_mut() {
+            Ok(callback) => callback,
+            Err(_) => return Ok(()),
+        };
+
+        callback(&mut interp, &name1, name2.as_ref().map(|s| s as &str), op)
+    });
+
+    match res {
+        Ok(()) => ptr::null_mut(),
+
+        // With `TCL_TRACE_RESULT_OBJECT`, Tcl takes ownership of the reference we hold.
+        Err(err) => {
+            let obj = err.message().to_tcl_obj();
+            let ptr = obj.as_ptr();
+            mem::forget(obj);
+            ptr as *mut c_char
+        }
+    }
+}
+
+impl TclInterp {
+    /// Call `callback` whenever one of the operations in `ops` happens to the variable `name`.
+    ///
+    /// `name` may refer to an array element, as in `name(elem)`. Tcl removes the trace by itself
+    /// when the variable is unset. A panic in `callback` is treated like an error.
+    ///
+    /// # Errors
+    /// This function fails if the trace can not be created, e.g. because `name` refers to an
+    /// element of a scalar variable.
+    pub fn trace_var<F>(
+        &mut self,
+        name: &str,
+        ops: TraceOps,
+        callback: F,
+    ) -> Result<VarTrace, TclError>
+    where
+        F: FnMut(&mut TclInterp, &str, Option<&str>, TraceOps) -> Result<(), TclError> + 'static,
+    {
+        let name = mutf8::to_cstring(name);
+        let flags = (ops | TraceOps::UNSET).0 | tcl_sys::TCL_TRACE_RESULT_OBJECT as c_int;
+
+        debug!("Tracing variable {:?} for {:?}", name, ops);
+
+        let data = Rc::new(TraceData {
+            interp: self.downgrade(),
+            ops,
+            callback: RefCell::new(Box::new(callback)),
+            destroyed: Cell::new(false),
+        });
+        let ptr = Rc::into_raw(data.clone());
+
+        let res = unsafe {
+            tcl_sys::Tcl_TraceVar2(
+                self.interp_ptr()?.as_ptr(),
+                name.as_ptr(),
+                ptr::null(),
+                flags,
+                Some(trace_callback),
+                ptr as *mut c_void,
+            )
+        };
+
+        if res as c_uint != tcl_sys::TCL_OK {
+            mem::drop(unsafe { Rc::from_raw(ptr) });
+            return Err(self.get_error()?);
+        }
+
+        Ok(VarTrace {
+            name,
+            flags,
+            data,
+            ptr,
+        })
+    }
+}
+
+impl Drop for VarTrace {
+    fn drop(&mut self) {
+        // Tcl already gave back its reference if it removed the trace on its own.
+        if self.data.destroyed.get() {
+            return;
+        }
+
+        // A deleted interpreter still calls the trace when it's finally freed, so the trace has to
+        // go even then. Freeing it destroys the trace, so until then the pointer is still valid.
+        debug!("Removing trace on {:?}", self.name);
+
+        unsafe {
+            tcl_sys::Tcl_UntraceVar2(
+                self.data.interp.as_ptr(),
+                self.name.as_ptr(),
+                ptr::null(),
+                self.flags,
+                Some(trace_callback),
+                self.ptr as *mut c_void,
+            )
+        };
+
+        // If the callback is running right now, it holds on to the data until it's done.
+        mem::drop(unsafe { Rc::from_raw(self.ptr) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Events = Rc<RefCell<Vec<(String, Option<String>, TraceOps)>>>;
+
+    fn recorder(
+        events: &Events,
+    ) -> impl FnMut(&mut TclInterp, &str, Option<&str>, TraceOps) -> Result<(), TclError> {
+        let events = events.clone();
+        move |_, name1, name2, op| {
+            events
+                .borrow_mut()
+                .push((name1.to_owned(), name2.map(str::to_owned), op));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_write_read() {
+        let mut interp = TclInterp::new().unwrap();
+        let events = Events::default();
+
+        let _trace = interp
+            .trace_var("x", TraceOps::WRITE | TraceOps::READ, recorder(&events))
+            .unwrap();
+
+        interp
+            .eval("set x 1; set x".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                ("x".to_owned(), None, TraceOps::WRITE),
+                ("x".to_owned(), None, TraceOps::READ)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_array() {
+        let mut interp = TclInterp::new().unwrap();
+        let events = Events::default();
+
+        let _trace = interp
+            .trace_var("a", TraceOps::WRITE | TraceOps::ARRAY, recorder(&events))
+            .unwrap();
+
+        interp
+            .eval("set a(k) v; array size a".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                ("a".to_owned(), Some("k".to_owned()), TraceOps::WRITE),
+                ("a".to_owned(), None, TraceOps::ARRAY)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_error() {
+        let mut interp = TclInterp::new().unwrap();
+
+        let _trace = interp
+            .trace_var("x", TraceOps::WRITE, |_, _, _, _| {
+                Err(TclError::new("read-only"))
+            })
+            .unwrap();
+
+        let err = interp.eval("set x 1".to_owned()).unwrap_err();
+        assert_eq!(err.message(), "can't set \"x\": read-only");
+    }
+
+    #[test]
+    fn test_trace_panic() {
+        let mut interp = TclInterp::new().unwrap();
+
+        let _trace = interp
+            .trace_var("x", TraceOps::READ, |_, _, _, _| panic!("no reading"))
+            .unwrap();
+
+        interp
+            .eval("set x 1".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        let err = interp.eval("set x".to_owned()).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "can't read \"x\": Variable trace panicked: no reading"
+        );
+    }
+
+    #[test]
+    fn test_trace_drop() {
+        let mut interp = TclInterp::new().unwrap();
+        let events = Events::default();
+
+        let trace = interp
+            .trace_var("x", TraceOps::WRITE, recorder(&events))
+            .unwrap();
+        interp
+            .eval("set x 1".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        mem::drop(trace);
+        interp
+            .eval("set x 2".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+
+        assert_eq!(events.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_trace_drop_from_callback() {
+        let mut interp = TclInterp::new().unwrap();
+        let calls = Rc::new(Cell::new(0));
+
+        let slot: Rc<RefCell<Option<VarTrace>>> = Default::default();
+        let trace = {
+            let (calls, slot) = (calls.clone(), slot.clone());
+            interp
+                .trace_var("x", TraceOps::WRITE, move |_, _, _, _| {
+                    calls.set(calls.get() + 1);
+                    mem::drop(slot.borrow_mut().take());
+
+                    // The closure must still be alive after dropping its own guard.
+                    calls.set(calls.get() + 1);
+                    Ok(())
+                })
+                .unwrap()
+        };
+        *slot.borrow_mut() = Some(trace);
+
+        interp
+            .eval("set x 1; set x 2".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(calls.get(), 2);
+        assert_eq!(Rc::strong_count(&calls), 1);
+    }
+
+    #[test]
+    fn test_trace_unset() {
+        let mut interp = TclInterp::new().unwrap();
+        let events = Events::default();
+
+        interp
+            .eval("set x 1".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        let trace = interp
+            .trace_var("x", TraceOps::UNSET, recorder(&events))
+            .unwrap();
+
+        interp
+            .eval("unset x; set x 2; unset x".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![("x".to_owned(), None, TraceOps::UNSET)]
+        );
+
+        // Tcl already removed the trace, so this must not touch it.
+        mem::drop(trace);
+    }
+
+    #[test]
+    fn test_trace_interp_deleted() {
+        let mut interp = TclInterp::new().unwrap();
+        let events = Events::default();
+
+        interp
+            .eval("set x 1".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        let trace = interp
+            .trace_var("x", TraceOps::WRITE, recorder(&events))
+            .unwrap();
+
+        interp.delete().unwrap();
+        mem::drop(trace);
+        assert!(events.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_trace_owned_by_interp() {
+        let mut interp = TclInterp::new().unwrap();
+        let events = Events::default();
+
+        let trace = interp
+            .trace_var("x", TraceOps::UNSET, recorder(&events))
+            .unwrap();
+        interp
+            .createcommand("keep", move |_, _| {
+                let _ = &trace;
+                Ok::<_, TclError>("")
+            })
+            .unwrap();
+
+        // The trace mustn't keep the interpreter alive, nor run while it's being dropped.
+        mem::drop(interp);
+        assert_eq!(Rc::strong_count(&events), 1);
+        assert!(events.borrow().is_empty());
+    }
+}
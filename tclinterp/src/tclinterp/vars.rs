@@ -0,0 +1,267 @@
+use std::ops::BitOr;
+
+use super::*;
+
+/// Flags controlling how variable names are looked up and how errors are reported.
+///
+/// Flags can be combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VarFlags(c_int);
+
+impl VarFlags {
+    /// Look the name up in the current procedure or namespace.
+    pub const NONE: VarFlags = VarFlags(0);
+
+    /// Only look the name up in the global namespace.
+    pub const GLOBAL_ONLY: VarFlags = VarFlags(tcl_sys::TCL_GLOBAL_ONLY as c_int);
+
+    /// Only look the name up in the current namespace, ignoring procedure variables.
+    pub const NAMESPACE_ONLY: VarFlags = VarFlags(tcl_sys::TCL_NAMESPACE_ONLY as c_int);
+
+    /// Report failures with Tcl's own error message (e.g. `can't read "x": no such variable`)
+    /// instead of a generic one.
+    pub const LEAVE_ERR_MSG: VarFlags = VarFlags(tcl_sys::TCL_LEAVE_ERR_MSG as c_int);
+
+    pub fn contains(self, other: VarFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn bits(self) -> c_int {
+        self.0
+    }
+}
+
+impl BitOr for VarFlags {
+    type Output = VarFlags;
+
+    fn bitor(self, other: VarFlags) -> VarFlags {
+        VarFlags(self.0 | other.0)
+    }
+}
+
+impl TclInterp {
+    /// Build the error for a failed variable operation.
+    fn var_error(&self, action: &str, name: &str, flags: VarFlags) -> TclError {
+        if flags.contains(VarFlags::LEAVE_ERR_MSG) {
+            self.get_error().unwrap_or_else(|e| e)
+        } else {
+            TclError::new(format!(
+                "Could not {} variable with name {:?}",
+                action, name
+            ))
+        }
+    }
+
+    /// Get the value of a variable.
+    ///
+    /// `name` may refer to an array element, as in `name(elem)`.
+    ///
+    /// # Errors
+    /// This function fails if the variable does not exist or if a read trace on it fails.
+    pub fn getvar(&self, name: &str, flags: VarFlags) -> Result<TclObj, TclError> {
+        let c_name = mutf8::to_cstring(name);
+
+        let ptr = unsafe {
+            tcl_sys::Tcl_GetVar2Ex(
+                self.interp_ptr()?.as_ptr(),
+                c_name.as_ptr(),
+                ptr::null(),
+                flags.bits(),
+            )
+        };
+
+        NonNull::new(ptr)
+            .map(TclObj::new)
+            .ok_or_else(|| self.var_error("get", name, flags))
+    }
+
+    /// Set the value of a variable, creating it if needed.
+    ///
+    /// `name` may refer to an array element, as in `name(elem)`. The new value of the variable is
+    /// returned, which may differ from `value` if a write trace changed it.
+    ///
+    /// # Errors
+    /// This function fails if the variable can not be set, e.g. because `name` refers to an array
+    /// element and the variable is a scalar.
+    pub fn setvar(
+        &mut self,
+        name: &str,
+        value: impl ToTclObj,
+        flags: VarFlags,
+    ) -> Result<TclObj, TclError> {
+        let c_name = mutf8::to_cstring(name);
+        let value = value.to_tcl_obj();
+
+        let ptr = unsafe {
+            tcl_sys::Tcl_SetVar2Ex(
+                self.interp_ptr()?.as_ptr(),
+                c_name.as_ptr(),
+                ptr::null(),
+                value.as_ptr(),
+                flags.bits(),
+            )
+        };
+
+        NonNull::new(ptr)
+            .map(TclObj::new)
+            .ok_or_else(|| self.var_error("set", name, flags))
+    }
+
+    /// Remove a variable.
+    ///
+    /// `name` may refer to an array element, as in `name(elem)`.
+    ///
+    /// # Errors
+    /// This function fails if the variable does not exist.
+    pub fn unsetvar(&mut self, name: &str, flags: VarFlags) -> Result<(), TclError> {
+        let c_name = mutf8::to_cstring(name);
+
+        let res = unsafe {
+            tcl_sys::Tcl_UnsetVar2(
+                self.interp_ptr()?.as_ptr(),
+                c_name.as_ptr(),
+                ptr::null(),
+                flags.bits(),
+            )
+        };
+
+        match res as c_uint {
+            tcl_sys::TCL_OK => Ok(()),
+            _ => Err(self.var_error("unset", name, flags)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scalar() {
+        let mut interp = TclInterp::new().unwrap();
+
+        interp.setvar("x", 42, VarFlags::NONE).unwrap();
+        assert_eq!(
+            interp
+                .eval("set x".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "42"
+        );
+
+        interp
+            .eval("set x hello".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(
+            interp.getvar("x", VarFlags::NONE).unwrap().to_string(),
+            "hello"
+        );
+
+        interp.unsetvar("x", VarFlags::NONE).unwrap();
+        assert!(interp.getvar("x", VarFlags::NONE).is_err());
+        assert!(interp.unsetvar("x", VarFlags::NONE).is_err());
+    }
+
+    #[test]
+    fn test_native_value() {
+        let mut interp = TclInterp::new().unwrap();
+
+        interp.setvar("l", vec![1, 2, 3], VarFlags::NONE).unwrap();
+        assert_eq!(
+            interp
+                .eval("llength $l".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "3"
+        );
+    }
+
+    #[test]
+    fn test_array_element() {
+        let mut interp = TclInterp::new().unwrap();
+
+        interp.setvar("a(x)", "1", VarFlags::NONE).unwrap();
+        interp.setvar("a(y z)", "2", VarFlags::NONE).unwrap();
+        assert_eq!(
+            interp
+                .eval("array size a".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "2"
+        );
+        assert_eq!(
+            interp.getvar("a(y z)", VarFlags::NONE).unwrap().to_string(),
+            "2"
+        );
+
+        interp.unsetvar("a(x)", VarFlags::NONE).unwrap();
+        assert_eq!(
+            interp
+                .eval("array names a".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "{y z}"
+        );
+
+        // `a` is an array, so it can't be read as a scalar.
+        assert!(interp.getvar("a", VarFlags::NONE).is_err());
+    }
+
+    #[test]
+    fn test_scope_flags() {
+        let mut interp = TclInterp::new().unwrap();
+
+        interp
+            .createcommand("check", |interp, _| {
+                interp.setvar("v", "local", VarFlags::NONE)?;
+                interp.setvar("v", "global", VarFlags::GLOBAL_ONLY)?;
+
+                interp.getvar("v", VarFlags::NONE)
+            })
+            .unwrap();
+
+        assert_eq!(
+            interp
+                .eval("proc p {} { check }; p".to_owned())
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "local"
+        );
+        assert_eq!(
+            interp
+                .getvar("v", VarFlags::GLOBAL_ONLY)
+                .unwrap()
+                .to_string(),
+            "global"
+        );
+
+        interp
+            .eval("namespace eval ns { variable w inside }".to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(
+            interp
+                .getvar("ns::w", VarFlags::NAMESPACE_ONLY)
+                .unwrap()
+                .to_string(),
+            "inside"
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let interp = TclInterp::new().unwrap();
+
+        let err = interp.getvar("missing", VarFlags::NONE).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Could not get variable with name \"missing\""
+        );
+
+        let err = interp
+            .getvar("missing", VarFlags::LEAVE_ERR_MSG)
+            .unwrap_err();
+        assert_eq!(err.message(), "can't read \"missing\": no such variable");
+    }
+}
@@ -1,24 +1,229 @@
-use std::{ffi::CStr, os::raw::*, ptr::NonNull};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    hash::Hash,
+    marker::PhantomData,
+    mem,
+    os::raw::*,
+    ptr::{self, NonNull},
+    slice,
+};
 
 use pyo3::types::{PyAny, PyString, PyTuple};
 
-use crate::wrappers::Objv;
+use crate::{
+    exceptions::{TclError, TclErrorKind},
+    mutf8,
+    wrappers::Objv,
+};
 
+// `Tcl_IncrRefCount` and `Tcl_DecrRefCount` are macros, so bindgen can't see them. We use the
+// functions that back them in memory-debugging builds instead, which behave exactly the same
+// otherwise (including freeing the object once its reference count drops to zero).
+macro_rules! incr_ref_count {
+    ($ptr:expr) => {
+        tcl_sys::Tcl_DbIncrRefCount(
+            $ptr,
+            concat!(file!(), "\0").as_ptr() as *const c_char,
+            line!() as c_int,
+        )
+    };
+}
+
+macro_rules! decr_ref_count {
+    ($ptr:expr) => {
+        tcl_sys::Tcl_DbDecrRefCount(
+            $ptr,
+            concat!(file!(), "\0").as_ptr() as *const c_char,
+            line!() as c_int,
+        )
+    };
+}
+
+/// Call one of Tcl's object constructors and wrap the new object.
+macro_rules! new_obj {
+    ($new:ident($($arg:expr),*)) => {{
+        crate::init_tcl();
+        TclObj::new(NonNull::new(unsafe { tcl_sys::$new($($arg),*) }).unwrap())
+    }};
+}
+
+/// An owned reference to a Tcl object.
+///
+/// Cloning a `TclObj` gives another reference to the same object, and the object is freed once
+/// the last reference to it (held by Rust or by Tcl) goes away.
 pub struct TclObj {
     ptr: NonNull<tcl_sys::Tcl_Obj>,
 }
 
 impl TclObj {
+    /// Wrap a pointer to a Tcl object, taking a new reference to it.
+    ///
+    /// This is suitable both for freshly created objects (with a reference count of zero) and for
+    /// objects that are owned by someone else, such as an interpreter's result.
     pub fn new(ptr: NonNull<tcl_sys::Tcl_Obj>) -> Self {
-        unsafe {
-            (*ptr.as_ptr()).refCount += 1;
-        }
+        unsafe { incr_ref_count!(ptr.as_ptr()) };
         TclObj { ptr }
     }
 
     pub fn as_ptr(&self) -> *mut tcl_sys::Tcl_Obj {
         self.ptr.as_ptr()
     }
+
+    /// Check whether anybody else (Rust or Tcl) holds a reference to this object.
+    ///
+    /// Shared objects must not be modified in place.
+    pub fn is_shared(&self) -> bool {
+        // This is what the `Tcl_IsShared` macro does.
+        unsafe { (*self.as_ptr()).refCount > 1 }
+    }
+
+    /// Make this the only reference to its object, copying the object if it is shared.
+    fn make_unshared(&mut self) {
+        if self.is_shared() {
+            let ptr = unsafe { tcl_sys::Tcl_DuplicateObj(self.as_ptr()) };
+            *self = TclObj::new(NonNull::new(ptr).unwrap());
+        }
+    }
+
+    /// Get the bytes of this object's string representation, in Tcl's modified UTF-8.
+    fn string_bytes(&self) -> &[u8] {
+        let mut len: c_int = 0;
+        let ptr = unsafe { tcl_sys::Tcl_GetStringFromObj(self.as_ptr(), &mut len) };
+
+        unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) }
+    }
+
+    /// Convert this object into a Rust value.
+    ///
+    /// # Errors
+    /// This function fails if the object can not be interpreted as a `T`.
+    pub fn extract<T: FromTclObj>(&self) -> Result<T, TclError> {
+        T::from_tcl_obj(self)
+    }
+}
+
+/// A type that can be extracted from a Tcl object.
+///
+/// Conversions are done without an interpreter, so the error messages are our own and not the ones
+/// Tcl would produce.
+pub trait FromTclObj: Sized {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError>;
+}
+
+fn conversion_error(obj: &TclObj, expected: &str) -> TclError {
+    TclError::with_kind(
+        TclErrorKind::Conversion,
+        format!("expected {} but got {:?}", expected, obj.to_string()),
+    )
+}
+
+impl FromTclObj for TclObj {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        Ok(obj.clone())
+    }
+}
+
+impl FromTclObj for String {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        Ok(obj.to_string())
+    }
+}
+
+impl FromTclObj for i32 {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        // `Tcl_GetIntFromObj` accepts anything up to `UINT_MAX` and wraps it around, so we check
+        // the range ourselves.
+        let mut value: tcl_sys::Tcl_WideInt = 0;
+
+        match unsafe { tcl_sys::Tcl_GetWideIntFromObj(ptr::null_mut(), obj.as_ptr(), &mut value) }
+            as c_uint
+        {
+            tcl_sys::TCL_OK => {
+                i32::try_from(value as i64).map_err(|_| conversion_error(obj, "integer"))
+            }
+            _ => Err(conversion_error(obj, "integer")),
+        }
+    }
+}
+
+impl FromTclObj for i64 {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        let mut value: tcl_sys::Tcl_WideInt = 0;
+
+        match unsafe { tcl_sys::Tcl_GetWideIntFromObj(ptr::null_mut(), obj.as_ptr(), &mut value) }
+            as c_uint
+        {
+            tcl_sys::TCL_OK => Ok(value as i64),
+            _ => Err(conversion_error(obj, "wide integer")),
+        }
+    }
+}
+
+impl FromTclObj for f64 {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        let mut value: c_double = 0.0;
+
+        match unsafe { tcl_sys::Tcl_GetDoubleFromObj(ptr::null_mut(), obj.as_ptr(), &mut value) }
+            as c_uint
+        {
+            tcl_sys::TCL_OK => Ok(value),
+            _ => Err(conversion_error(obj, "floating-point number")),
+        }
+    }
+}
+
+impl FromTclObj for bool {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        let mut value: c_int = 0;
+
+        match unsafe { tcl_sys::Tcl_GetBooleanFromObj(ptr::null_mut(), obj.as_ptr(), &mut value) }
+            as c_uint
+        {
+            tcl_sys::TCL_OK => Ok(value != 0),
+            _ => Err(conversion_error(obj, "boolean")),
+        }
+    }
+}
+
+/// The empty string is `None`, anything else is converted as a `T`.
+impl<T: FromTclObj> FromTclObj for Option<T> {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        if obj.string_bytes().is_empty() {
+            Ok(None)
+        } else {
+            T::from_tcl_obj(obj).map(Some)
+        }
+    }
+}
+
+impl<T: FromTclObj> FromTclObj for Vec<T> {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        TclList::from_tcl_obj(obj)?
+            .iter()
+            .map(|element| element.extract())
+            .collect()
+    }
+}
+
+impl<K, V> FromTclObj for HashMap<K, V>
+where
+    K: FromTclObj + Eq + Hash,
+    V: FromTclObj,
+{
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        TclDict::from_tcl_obj(obj)?.extract_pairs()
+    }
+}
+
+impl<K, V> FromTclObj for BTreeMap<K, V>
+where
+    K: FromTclObj + Ord,
+    V: FromTclObj,
+{
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        TclDict::from_tcl_obj(obj)?.extract_pairs()
+    }
 }
 
 // Is `IntoTclObj` a better name for this?
@@ -33,46 +238,173 @@ impl ToTclObj for TclObj {
 }
 
 // XXX: This feels like a weird instance.
-// It's basically meant for &&str
+// It's basically meant for &&str and &String.
 impl<T> ToTclObj for &T
 where
-    T: Copy + ToTclObj,
+    T: Clone + ToTclObj,
 {
     fn to_tcl_obj(self) -> TclObj {
         self.clone().to_tcl_obj()
     }
 }
 
+/// Create a Tcl string object out of UTF-8 bytes, which may contain NUL.
+fn new_string(bytes: &[u8]) -> TclObj {
+    let bytes = mutf8::encode(bytes);
+
+    // `Tcl_NewStringObj` copies its argument.
+    new_obj!(Tcl_NewStringObj(
+        bytes.as_ptr() as *const c_char,
+        bytes.len() as c_int
+    ))
+}
+
 impl ToTclObj for &[u8] {
     fn to_tcl_obj(self) -> TclObj {
-        // `Tcl_NewStringObj` copies its argument.
-        let ptr = unsafe {
-            tcl_sys::Tcl_NewStringObj(self.as_ptr() as *const c_char, self.len() as c_int)
-        };
-        TclObj::new(NonNull::new(ptr).unwrap())
+        TclByteArray::new(self).to_tcl_obj()
     }
 }
 
 impl ToTclObj for &str {
     fn to_tcl_obj(self) -> TclObj {
-        self.as_bytes().to_tcl_obj()
+        new_string(self.as_bytes())
     }
 }
 
-impl ToTclObj for &PyString {
+impl ToTclObj for String {
     fn to_tcl_obj(self) -> TclObj {
-        self.as_bytes().to_tcl_obj()
+        self.as_str().to_tcl_obj()
     }
 }
 
-impl ToTclObj for &PyTuple {
+macro_rules! int_to_tcl_obj {
+    ($new:ident as $c_ty:ty => $($ty:ty),*) => {
+        $(
+            impl ToTclObj for $ty {
+                fn to_tcl_obj(self) -> TclObj {
+                    new_obj!($new(self as $c_ty))
+                }
+            }
+        )*
+    };
+}
+
+// `u8` is deliberately missing, so that `&[u8]` can keep meaning a byte array instead of a list of
+// integers.
+int_to_tcl_obj!(Tcl_NewIntObj as c_int => i8, i16, i32, u16);
+int_to_tcl_obj!(Tcl_NewWideIntObj as tcl_sys::Tcl_WideInt => i64, isize, u32);
+
+macro_rules! big_int_to_tcl_obj {
+    ($($ty:ty),*) => {
+        $(
+            impl ToTclObj for $ty {
+                fn to_tcl_obj(self) -> TclObj {
+                    // Tcl has no unsigned or 128-bit wide integers, so anything that doesn't fit
+                    // in a signed 64-bit one is passed as its decimal representation and Tcl
+                    // makes a bignum out of it when needed.
+                    match i64::try_from(self) {
+                        Ok(value) => value.to_tcl_obj(),
+                        Err(_) => self.to_string().to_tcl_obj(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+big_int_to_tcl_obj!(u64, usize, i128, u128);
+
+impl ToTclObj for f64 {
+    fn to_tcl_obj(self) -> TclObj {
+        new_obj!(Tcl_NewDoubleObj(self))
+    }
+}
+
+impl ToTclObj for f32 {
+    fn to_tcl_obj(self) -> TclObj {
+        f64::from(self).to_tcl_obj()
+    }
+}
+
+impl ToTclObj for bool {
+    fn to_tcl_obj(self) -> TclObj {
+        new_obj!(Tcl_NewBooleanObj(self as c_int))
+    }
+}
+
+/// `None` becomes the empty string, which is what `FromTclObj` turns back into `None`.
+impl<T: ToTclObj> ToTclObj for Option<T> {
+    fn to_tcl_obj(self) -> TclObj {
+        match self {
+            Some(value) => value.to_tcl_obj(),
+            None => new_obj!(Tcl_NewObj()),
+        }
+    }
+}
+
+fn new_list<I>(it: I) -> TclObj
+where
+    I: IntoIterator,
+    I::Item: ToTclObj,
+{
+    let objv = Objv::new(it);
+
+    // `Tcl_NewListObj` increments the reference count of every element.
+    new_obj!(Tcl_NewListObj(objv.len(), objv.as_ptr()))
+}
+
+impl<T: ToTclObj> ToTclObj for Vec<T> {
     fn to_tcl_obj(self) -> TclObj {
-        let objv = Objv::new(self);
+        new_list(self)
+    }
+}
+
+impl<T: Clone + ToTclObj> ToTclObj for &[T] {
+    fn to_tcl_obj(self) -> TclObj {
+        new_list(self.iter().cloned())
+    }
+}
 
-        let ptr = unsafe { tcl_sys::Tcl_NewListObj(objv.len(), objv.as_ptr()) };
-        let ptr = NonNull::new(ptr).unwrap();
+macro_rules! tuple_to_tcl_obj {
+    ($($name:ident),+) => {
+        impl<$($name: ToTclObj),+> ToTclObj for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn to_tcl_obj(self) -> TclObj {
+                let ($($name,)+) = self;
+                new_list(vec![$($name.to_tcl_obj()),+])
+            }
+        }
+    };
+}
+
+tuple_to_tcl_obj!(A);
+tuple_to_tcl_obj!(A, B);
+tuple_to_tcl_obj!(A, B, C);
+tuple_to_tcl_obj!(A, B, C, D);
+tuple_to_tcl_obj!(A, B, C, D, E);
+tuple_to_tcl_obj!(A, B, C, D, E, F);
+
+impl<K: ToTclObj, V: ToTclObj> ToTclObj for HashMap<K, V> {
+    fn to_tcl_obj(self) -> TclObj {
+        self.into_iter().collect::<TclDict>().to_tcl_obj()
+    }
+}
 
-        TclObj::new(ptr)
+impl<K: ToTclObj, V: ToTclObj> ToTclObj for BTreeMap<K, V> {
+    fn to_tcl_obj(self) -> TclObj {
+        self.into_iter().collect::<TclDict>().to_tcl_obj()
+    }
+}
+
+impl ToTclObj for &PyString {
+    fn to_tcl_obj(self) -> TclObj {
+        new_string(self.as_bytes())
+    }
+}
+
+impl ToTclObj for &PyTuple {
+    fn to_tcl_obj(self) -> TclObj {
+        new_list(self)
     }
 }
 
@@ -90,34 +422,878 @@ impl ToTclObj for &PyAny {
 
 impl std::fmt::Display for TclObj {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let s = unsafe { CStr::from_ptr(tcl_sys::Tcl_GetString(self.as_ptr())) };
-        let s = s.to_str().unwrap();
-
-        write!(f, "{}", s)
+        write!(f, "{}", mutf8::decode(self.string_bytes()))
     }
 }
 
 impl std::fmt::Debug for TclObj {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        unsafe {
-            let ptr = self.as_ptr();
-
-            f.debug_struct("TclObjWrapper")
-                .field("ptr", &format!("{:#p}", ptr))
-                .field("refCount", &(*ptr).refCount)
-                .field(
-                    "str",
-                    &std::ffi::CStr::from_ptr(tcl_sys::Tcl_GetString(ptr)),
-                )
-                .finish()
-        }
+        let ptr = self.as_ptr();
+
+        f.debug_struct("TclObjWrapper")
+            .field("ptr", &format!("{:#p}", ptr))
+            .field("refCount", unsafe { &(*ptr).refCount })
+            .field("str", &mutf8::decode(self.string_bytes()))
+            .finish()
+    }
+}
+
+impl Clone for TclObj {
+    fn clone(&self) -> Self {
+        TclObj::new(self.ptr)
     }
 }
 
 impl Drop for TclObj {
     fn drop(&mut self) {
+        unsafe { decr_ref_count!(self.as_ptr()) };
+    }
+}
+
+/// A Tcl list.
+///
+/// Like `TclDict`, modifying a `TclList` copies the underlying object first if it is shared.
+#[derive(Debug, Clone)]
+pub struct TclList(TclObj);
+
+impl TclList {
+    /// Create a new, empty list.
+    pub fn new() -> Self {
+        TclList(new_obj!(Tcl_NewListObj(0, ptr::null())))
+    }
+
+    /// Interpret an object as a list.
+    ///
+    /// # Errors
+    /// This function fails if `obj` is not a valid Tcl list.
+    pub fn from_obj(obj: TclObj) -> Result<Self, TclError> {
+        let mut len: c_int = 0;
+
+        match unsafe { tcl_sys::Tcl_ListObjLength(ptr::null_mut(), obj.as_ptr(), &mut len) }
+            as c_uint
+        {
+            tcl_sys::TCL_OK => Ok(TclList(obj)),
+            _ => Err(conversion_error(&obj, "list")),
+        }
+    }
+
+    /// Get the number of elements in the list.
+    pub fn len(&self) -> usize {
+        let mut len: c_int = 0;
+
+        let res = unsafe { tcl_sys::Tcl_ListObjLength(ptr::null_mut(), self.0.as_ptr(), &mut len) };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclList holds a non-list");
+
+        len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the element at `index`, if there is one.
+    pub fn get(&self, index: usize) -> Option<TclObj> {
+        // Tcl lists can't have more elements than fit in an int.
+        let index = c_int::try_from(index).ok()?;
+        let mut element: *mut tcl_sys::Tcl_Obj = ptr::null_mut();
+
+        let res = unsafe {
+            tcl_sys::Tcl_ListObjIndex(ptr::null_mut(), self.0.as_ptr(), index, &mut element)
+        };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclList holds a non-list");
+
+        NonNull::new(element).map(TclObj::new)
+    }
+
+    /// Add an element to the end of the list.
+    pub fn push(&mut self, value: impl ToTclObj) {
+        let value = value.to_tcl_obj();
+        self.0.make_unshared();
+
+        let res = unsafe {
+            tcl_sys::Tcl_ListObjAppendElement(ptr::null_mut(), self.0.as_ptr(), value.as_ptr())
+        };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclList holds a non-list");
+    }
+
+    /// Replace `count` elements starting at `first` with the elements of `it`.
+    ///
+    /// Like `lreplace`, `first` may be equal to the length of the list to append, and `count` is
+    /// clamped to the elements that actually exist.
+    pub fn replace<I>(&mut self, first: usize, count: usize, it: I)
+    where
+        I: IntoIterator,
+        I::Item: ToTclObj,
+    {
+        let objv = Objv::new(it);
+        self.0.make_unshared();
+
+        // Tcl clamps these too, as long as they fit in an int.
+        let clamp = |n: usize| c_int::try_from(n).unwrap_or(c_int::max_value());
+
+        let res = unsafe {
+            tcl_sys::Tcl_ListObjReplace(
+                ptr::null_mut(),
+                self.0.as_ptr(),
+                clamp(first),
+                clamp(count),
+                objv.len(),
+                objv.as_ptr(),
+            )
+        };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclList holds a non-list");
+    }
+
+    /// Iterate over the elements of the list.
+    pub fn iter(&self) -> TclListIter<'_> {
+        TclListIter {
+            list: self,
+            index: 0,
+        }
+    }
+}
+
+impl Default for TclList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ToTclObj> std::iter::FromIterator<T> for TclList {
+    fn from_iter<I: IntoIterator<Item = T>>(it: I) -> Self {
+        TclList(new_list(it))
+    }
+}
+
+impl<'a> IntoIterator for &'a TclList {
+    type Item = TclObj;
+    type IntoIter = TclListIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl ToTclObj for TclList {
+    fn to_tcl_obj(self) -> TclObj {
+        self.0
+    }
+}
+
+impl FromTclObj for TclList {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        TclList::from_obj(obj.clone())
+    }
+}
+
+/// An iterator over the elements of a `TclList`.
+pub struct TclListIter<'a> {
+    list: &'a TclList,
+    index: usize,
+}
+
+impl Iterator for TclListIter<'_> {
+    type Item = TclObj;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let element = self.list.get(self.index)?;
+        self.index += 1;
+        Some(element)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.list.len().saturating_sub(self.index);
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for TclListIter<'_> {}
+
+/// A Tcl dictionary.
+///
+/// Modifying a `TclDict` never affects other references to the same object: if the object is
+/// shared, it is copied first.
+#[derive(Debug, Clone)]
+pub struct TclDict(TclObj);
+
+impl TclDict {
+    /// Create a new, empty dictionary.
+    pub fn new() -> Self {
+        TclDict(new_obj!(Tcl_NewDictObj()))
+    }
+
+    /// Interpret an object as a dictionary.
+    ///
+    /// # Errors
+    /// This function fails if `obj` is not a valid Tcl dictionary.
+    pub fn from_obj(obj: TclObj) -> Result<Self, TclError> {
+        let mut size: c_int = 0;
+
+        match unsafe { tcl_sys::Tcl_DictObjSize(ptr::null_mut(), obj.as_ptr(), &mut size) }
+            as c_uint
+        {
+            tcl_sys::TCL_OK => Ok(TclDict(obj)),
+            _ => Err(conversion_error(&obj, "dict")),
+        }
+    }
+
+    /// Look up the value for `key`.
+    pub fn get(&self, key: impl ToTclObj) -> Option<TclObj> {
+        let key = key.to_tcl_obj();
+        let mut value: *mut tcl_sys::Tcl_Obj = ptr::null_mut();
+
+        let res = unsafe {
+            tcl_sys::Tcl_DictObjGet(ptr::null_mut(), self.0.as_ptr(), key.as_ptr(), &mut value)
+        };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclDict holds a non-dict");
+
+        NonNull::new(value).map(TclObj::new)
+    }
+
+    /// Set the value for `key`, replacing any previous one.
+    pub fn put(&mut self, key: impl ToTclObj, value: impl ToTclObj) {
+        let (key, value) = (key.to_tcl_obj(), value.to_tcl_obj());
+        self.0.make_unshared();
+
+        let res = unsafe {
+            tcl_sys::Tcl_DictObjPut(
+                ptr::null_mut(),
+                self.0.as_ptr(),
+                key.as_ptr(),
+                value.as_ptr(),
+            )
+        };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclDict holds a non-dict");
+    }
+
+    /// Remove `key` and its value, returning the value if there was one.
+    pub fn remove(&mut self, key: impl ToTclObj) -> Option<TclObj> {
+        let key = key.to_tcl_obj();
+        let value = self.get(key.clone())?;
+        self.0.make_unshared();
+
+        let res =
+            unsafe { tcl_sys::Tcl_DictObjRemove(ptr::null_mut(), self.0.as_ptr(), key.as_ptr()) };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclDict holds a non-dict");
+
+        Some(value)
+    }
+
+    /// Get the number of entries in the dictionary.
+    pub fn len(&self) -> usize {
+        let mut size: c_int = 0;
+
+        let res = unsafe { tcl_sys::Tcl_DictObjSize(ptr::null_mut(), self.0.as_ptr(), &mut size) };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclDict holds a non-dict");
+
+        size as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the entries of the dictionary, in insertion order.
+    pub fn iter(&self) -> TclDictIter<'_> {
+        let mut iter = TclDictIter {
+            search: unsafe { mem::zeroed() },
+            next: None,
+            _dict: PhantomData,
+        };
+
+        let mut key: *mut tcl_sys::Tcl_Obj = ptr::null_mut();
+        let mut value: *mut tcl_sys::Tcl_Obj = ptr::null_mut();
+        let mut done: c_int = 0;
+
+        let res = unsafe {
+            tcl_sys::Tcl_DictObjFirst(
+                ptr::null_mut(),
+                self.0.as_ptr(),
+                &mut iter.search,
+                &mut key,
+                &mut value,
+                &mut done,
+            )
+        };
+        assert_eq!(res as c_uint, tcl_sys::TCL_OK, "TclDict holds a non-dict");
+
+        iter.set_next(key, value, done);
+        iter
+    }
+
+    /// Convert every entry of the dictionary into Rust values.
+    fn extract_pairs<K, V, C>(&self) -> Result<C, TclError>
+    where
+        K: FromTclObj,
+        V: FromTclObj,
+        C: std::iter::FromIterator<(K, V)>,
+    {
+        self.iter()
+            .map(|(key, value)| Ok((key.extract()?, value.extract()?)))
+            .collect()
+    }
+}
+
+impl Default for TclDict {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: ToTclObj, V: ToTclObj> std::iter::FromIterator<(K, V)> for TclDict {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(it: I) -> Self {
+        let mut dict = TclDict::new();
+        for (key, value) in it {
+            dict.put(key, value);
+        }
+        dict
+    }
+}
+
+impl<'a> IntoIterator for &'a TclDict {
+    type Item = (TclObj, TclObj);
+    type IntoIter = TclDictIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl ToTclObj for TclDict {
+    fn to_tcl_obj(self) -> TclObj {
+        self.0
+    }
+}
+
+impl FromTclObj for TclDict {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        TclDict::from_obj(obj.clone())
+    }
+}
+
+/// An iterator over the entries of a `TclDict`.
+pub struct TclDictIter<'a> {
+    search: tcl_sys::Tcl_DictSearch,
+    next: Option<(TclObj, TclObj)>,
+    _dict: PhantomData<&'a TclDict>,
+}
+
+impl TclDictIter<'_> {
+    fn set_next(&mut self, key: *mut tcl_sys::Tcl_Obj, value: *mut tcl_sys::Tcl_Obj, done: c_int) {
+        self.next = if done == 0 {
+            Some((
+                TclObj::new(NonNull::new(key).expect("Tcl_DictObjNext() returned NULL")),
+                TclObj::new(NonNull::new(value).expect("Tcl_DictObjNext() returned NULL")),
+            ))
+        } else {
+            None
+        };
+    }
+}
+
+impl Iterator for TclDictIter<'_> {
+    type Item = (TclObj, TclObj);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.next.take()?;
+
+        let mut key: *mut tcl_sys::Tcl_Obj = ptr::null_mut();
+        let mut value: *mut tcl_sys::Tcl_Obj = ptr::null_mut();
+        let mut done: c_int = 0;
+
+        unsafe { tcl_sys::Tcl_DictObjNext(&mut self.search, &mut key, &mut value, &mut done) };
+        self.set_next(key, value, done);
+
+        Some(item)
+    }
+}
+
+impl Drop for TclDictIter<'_> {
+    fn drop(&mut self) {
+        // This is harmless if the search has already finished.
+        unsafe { tcl_sys::Tcl_DictObjDone(&mut self.search) };
+    }
+}
+
+/// A Tcl object holding raw bytes, as opposed to text.
+///
+/// This is what Tcl uses for binary data, such as what is read from a channel configured with
+/// `-translation binary`.
+#[derive(Debug, Clone)]
+pub struct TclByteArray(TclObj);
+
+impl TclByteArray {
+    /// Create a new byte array with a copy of `bytes`.
+    pub fn new(bytes: &[u8]) -> Self {
+        // `Tcl_NewByteArrayObj` copies its argument.
+        TclByteArray(new_obj!(Tcl_NewByteArrayObj(
+            bytes.as_ptr(),
+            bytes.len() as c_int
+        )))
+    }
+
+    /// Interpret any object as a byte array.
+    ///
+    /// Like Tcl's `binary` commands, characters above `\u00FF` are truncated to their low byte.
+    pub fn from_obj(obj: TclObj) -> Self {
+        TclByteArray(obj)
+    }
+
+    /// Get the bytes stored in this byte array.
+    pub fn as_bytes(&self) -> &[u8] {
+        let mut len: c_int = 0;
+
+        // We ask Tcl each time instead of caching the pointer because somebody else holding a
+        // reference to the object may have converted it to another type in the meantime.
+        let ptr = unsafe { tcl_sys::Tcl_GetByteArrayFromObj(self.0.as_ptr(), &mut len) };
+
+        unsafe { slice::from_raw_parts(ptr, len as usize) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ToTclObj for TclByteArray {
+    fn to_tcl_obj(self) -> TclObj {
+        self.0
+    }
+}
+
+impl FromTclObj for TclByteArray {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        Ok(TclByteArray::from_obj(obj.clone()))
+    }
+}
+
+impl FromTclObj for Vec<u8> {
+    fn from_tcl_obj(obj: &TclObj) -> Result<Self, TclError> {
+        Ok(TclByteArray::from_tcl_obj(obj)?.as_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        ffi::CStr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::tclinterp::{Completion, TclInterp};
+
+    fn eval_obj(code: &str) -> TclObj {
+        let mut interp = TclInterp::new().unwrap();
+        let result = interp
+            .eval(code.to_owned())
+            .and_then(Completion::into_result)
+            .unwrap();
+        result.as_str().to_tcl_obj()
+    }
+
+    fn type_name(obj: &TclObj) -> Option<String> {
         unsafe {
-            (*self.as_ptr()).refCount -= 1;
+            let type_ptr = (*obj.as_ptr()).typePtr;
+
+            if type_ptr.is_null() {
+                None
+            } else {
+                Some(
+                    CStr::from_ptr((*type_ptr).name)
+                        .to_str()
+                        .unwrap()
+                        .to_owned(),
+                )
+            }
         }
     }
+
+    #[test]
+    fn test_extract_numbers() {
+        assert_eq!(eval_obj("expr {6 * 7}").extract::<i32>().unwrap(), 42);
+        assert_eq!(
+            eval_obj("expr {2 ** 40}").extract::<i64>().unwrap(),
+            1 << 40
+        );
+        assert_eq!(eval_obj("expr {1.0 / 4}").extract::<f64>().unwrap(), 0.25);
+        assert!(eval_obj("format abc").extract::<i32>().is_err());
+    }
+
+    #[test]
+    fn test_extract_i32_range() {
+        assert_eq!(
+            eval_obj("format 2147483647").extract::<i32>().unwrap(),
+            i32::max_value()
+        );
+        assert_eq!(
+            eval_obj("format -2147483648").extract::<i32>().unwrap(),
+            i32::min_value()
+        );
+        assert!(eval_obj("format 2147483648").extract::<i32>().is_err());
+        assert!(eval_obj("format -2147483649").extract::<i32>().is_err());
+        assert!(eval_obj("format 3000000000").extract::<i32>().is_err());
+        assert!(eval_obj("format 4294967295").extract::<i32>().is_err());
+    }
+
+    #[test]
+    fn test_extract_bool() {
+        assert!(eval_obj("format yes").extract::<bool>().unwrap());
+        assert!(!eval_obj("format 0").extract::<bool>().unwrap());
+        assert!(eval_obj("format maybe").extract::<bool>().is_err());
+    }
+
+    #[test]
+    fn test_extract_option() {
+        assert_eq!(
+            eval_obj("format {}").extract::<Option<i32>>().unwrap(),
+            None
+        );
+        assert_eq!(
+            eval_obj("format 3").extract::<Option<i32>>().unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_extract_list() {
+        assert_eq!(
+            eval_obj("list 1 2 3").extract::<Vec<i32>>().unwrap(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            eval_obj("list a {b c}").extract::<Vec<String>>().unwrap(),
+            vec!["a".to_owned(), "b c".to_owned()]
+        );
+        assert!(eval_obj("format \"{\"").extract::<Vec<String>>().is_err());
+    }
+
+    #[test]
+    fn test_extract_dict() {
+        let map = eval_obj("dict create a 1 b 2")
+            .extract::<HashMap<String, i32>>()
+            .unwrap();
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], 1);
+        assert_eq!(map["b"], 2);
+        assert!(eval_obj("list a b c")
+            .extract::<HashMap<String, String>>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_to_tcl_obj_numbers() {
+        let obj = 42.to_tcl_obj();
+        assert_eq!(type_name(&obj).as_ref().map(|s| s as &str), Some("int"));
+        assert_eq!(obj.extract::<i32>().unwrap(), 42);
+
+        assert_eq!((-7i8).to_tcl_obj().extract::<i32>().unwrap(), -7);
+        assert_eq!((1i64 << 40).to_tcl_obj().extract::<i64>().unwrap(), 1 << 40);
+        assert_eq!(
+            u64::max_value().to_tcl_obj().to_string(),
+            u64::max_value().to_string()
+        );
+        assert_eq!((-5i128).to_tcl_obj().extract::<i32>().unwrap(), -5);
+        assert_eq!(
+            i128::min_value().to_tcl_obj().to_string(),
+            i128::min_value().to_string()
+        );
+        assert_eq!(
+            u128::max_value().to_tcl_obj().to_string(),
+            u128::max_value().to_string()
+        );
+
+        let obj = 0.5.to_tcl_obj();
+        assert_eq!(type_name(&obj).as_ref().map(|s| s as &str), Some("double"));
+        assert_eq!(obj.extract::<f64>().unwrap(), 0.5);
+
+        assert!(true.to_tcl_obj().extract::<bool>().unwrap());
+        assert!(!false.to_tcl_obj().extract::<bool>().unwrap());
+    }
+
+    #[test]
+    fn test_to_tcl_obj_option() {
+        assert_eq!(
+            None::<i32>.to_tcl_obj().extract::<Option<i32>>().unwrap(),
+            None
+        );
+        assert_eq!(
+            Some(3).to_tcl_obj().extract::<Option<i32>>().unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_to_tcl_obj_list() {
+        let obj = vec![1, 2, 3].to_tcl_obj();
+        assert_eq!(type_name(&obj).as_ref().map(|s| s as &str), Some("list"));
+        assert_eq!(obj.to_string(), "1 2 3");
+
+        assert_eq!((&["a", "b c"][..]).to_tcl_obj().to_string(), "a {b c}");
+        assert_eq!(("a", 1, 2.5).to_tcl_obj().to_string(), "a 1 2.5");
+    }
+
+    #[test]
+    fn test_to_tcl_obj_dict() {
+        let mut map = BTreeMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+
+        let obj = map.to_tcl_obj();
+        assert_eq!(type_name(&obj).as_ref().map(|s| s as &str), Some("dict"));
+        assert_eq!(obj.to_string(), "a 1 b 2");
+
+        let mut map = HashMap::new();
+        map.insert("x".to_owned(), vec![1, 2]);
+        assert_eq!(
+            map.to_tcl_obj()
+                .extract::<HashMap<String, Vec<i32>>>()
+                .unwrap(),
+            {
+                let mut expected = HashMap::new();
+                expected.insert("x".to_owned(), vec![1, 2]);
+                expected
+            }
+        );
+    }
+
+    #[test]
+    fn test_nul_roundtrip() {
+        let obj = "a\0b".to_tcl_obj();
+        assert_eq!(obj.string_bytes(), b"a\xC0\x80b");
+        assert_eq!(obj.to_string(), "a\0b");
+        assert_eq!(
+            vec!["x\0", "y"]
+                .to_tcl_obj()
+                .extract::<Vec<String>>()
+                .unwrap(),
+            vec!["x\0".to_owned(), "y".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_byte_array() {
+        let data = (0..=255).collect::<Vec<u8>>();
+
+        let obj = (&data as &[u8]).to_tcl_obj();
+        assert_eq!(
+            type_name(&obj).as_ref().map(|s| s as &str),
+            Some("bytearray")
+        );
+        assert_eq!(obj.extract::<Vec<u8>>().unwrap(), data);
+
+        let mut interp = TclInterp::new().unwrap();
+        let len = interp
+            .call_obj(vec!["string".to_tcl_obj(), "length".to_tcl_obj(), obj])
+            .and_then(Completion::into_result)
+            .unwrap();
+        assert_eq!(len.extract::<i32>().unwrap(), 256);
+
+        let bytes = interp
+            .call_obj(&["binary", "format", "H*", "00ff7f"])
+            .and_then(Completion::into_result)
+            .unwrap()
+            .extract::<TclByteArray>()
+            .unwrap();
+        assert_eq!(bytes.as_bytes(), b"\x00\xff\x7f");
+    }
+
+    #[test]
+    fn test_list() {
+        let mut list = TclList::new();
+        assert!(list.is_empty());
+
+        list.push("a");
+        list.push(2);
+        list.push(vec!["b", "c"]);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.get(1).unwrap().extract::<i32>().unwrap(), 2);
+        assert!(list.get(3).is_none());
+
+        list.replace(0, 2, vec!["x", "y", "z"]);
+        assert_eq!(list.clone().to_tcl_obj().to_string(), "x y z {b c}");
+
+        list.replace(4, 0, vec!["end"]);
+        list.replace(1, 100, Vec::<TclObj>::new());
+        assert_eq!(list.clone().to_tcl_obj().to_string(), "x");
+
+        // Indices that don't fit in an int mustn't wrap around.
+        let huge = c_int::max_value() as usize + 1;
+        assert!(list.get(huge).is_none());
+        assert!(list.get(usize::max_value()).is_none());
+        #[cfg(target_pointer_width = "64")]
+        assert!(list.get(1 << 32).is_none());
+
+        list.replace(huge, 0, vec!["end"]);
+        assert_eq!(list.clone().to_tcl_obj().to_string(), "x end");
+        list.replace(1, usize::max_value(), Vec::<TclObj>::new());
+        assert_eq!(list.to_tcl_obj().to_string(), "x");
+    }
+
+    #[test]
+    fn test_list_iter() {
+        let list = TclList::from_obj("a {b c} d".to_tcl_obj()).unwrap();
+
+        let iter = list.iter();
+        assert_eq!(iter.len(), 3);
+        assert_eq!(
+            iter.map(|element| element.to_string()).collect::<Vec<_>>(),
+            vec!["a".to_owned(), "b c".to_owned(), "d".to_owned()]
+        );
+
+        assert!(TclList::from_obj("a {b".to_tcl_obj()).is_err());
+        assert_eq!(
+            (1..=3).collect::<TclList>().to_tcl_obj().to_string(),
+            "1 2 3"
+        );
+    }
+
+    #[test]
+    fn test_list_copy_on_write() {
+        let original = TclList::from_obj("a b".to_tcl_obj()).unwrap();
+        let mut copy = original.clone();
+        assert!(original.0.is_shared());
+
+        copy.push("c");
+        assert_eq!(original.len(), 2);
+        assert_eq!(copy.len(), 3);
+        assert!(!copy.0.is_shared());
+    }
+
+    #[test]
+    fn test_dict() {
+        let mut dict = TclDict::new();
+        assert!(dict.is_empty());
+
+        dict.put("a", 1);
+        dict.put("b", 2);
+        dict.put("a", 3);
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("a").unwrap().extract::<i32>().unwrap(), 3);
+        assert!(dict.get("c").is_none());
+
+        assert_eq!(dict.remove("b").unwrap().extract::<i32>().unwrap(), 2);
+        assert!(dict.remove("b").is_none());
+        assert_eq!(dict.to_tcl_obj().to_string(), "a 3");
+    }
+
+    #[test]
+    fn test_dict_iter() {
+        let dict = TclDict::from_obj("x 1 y 2 z 3".to_tcl_obj()).unwrap();
+
+        let pairs = dict
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.extract::<i32>().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![
+                ("x".to_owned(), 1),
+                ("y".to_owned(), 2),
+                ("z".to_owned(), 3)
+            ]
+        );
+
+        // Stopping early must not leave the search dangling.
+        assert_eq!(dict.iter().next().unwrap().0.to_string(), "x");
+        assert!(TclDict::from_obj("a b c".to_tcl_obj()).is_err());
+    }
+
+    #[test]
+    fn test_dict_copy_on_write() {
+        let original = TclDict::from_obj("a 1".to_tcl_obj()).unwrap();
+        let mut copy = original.clone();
+
+        copy.put("b", 2);
+        assert_eq!(original.len(), 1);
+        assert_eq!(copy.len(), 2);
+
+        let map = copy.extract_pairs::<String, i32, BTreeMap<_, _>>().unwrap();
+        assert_eq!(
+            map.into_iter().collect::<Vec<_>>(),
+            vec![("a".to_owned(), 1), ("b".to_owned(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_call_with_native_objs() {
+        let mut interp = TclInterp::new().unwrap();
+
+        assert_eq!(
+            interp
+                .call(vec![
+                    "expr".to_tcl_obj(),
+                    6.to_tcl_obj(),
+                    "*".to_tcl_obj(),
+                    7.to_tcl_obj()
+                ])
+                .and_then(Completion::into_result)
+                .unwrap(),
+            "42"
+        );
+    }
+
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn count_free(_: *mut tcl_sys::Tcl_Obj) {
+        FREED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    struct SyncObjType(tcl_sys::Tcl_ObjType);
+    unsafe impl Sync for SyncObjType {}
+
+    static TRACKED_TYPE: SyncObjType = SyncObjType(tcl_sys::Tcl_ObjType {
+        name: b"tracked\0" as *const u8 as *const c_char,
+        freeIntRepProc: Some(count_free),
+        dupIntRepProc: None,
+        updateStringProc: None,
+        setFromAnyProc: None,
+    });
+
+    /// Create an object whose internal representation counts how many times it's been freed.
+    fn tracked_obj() -> TclObj {
+        let obj = "tracked".to_tcl_obj();
+        unsafe { (*obj.as_ptr()).typePtr = &TRACKED_TYPE.0 };
+        obj
+    }
+
+    #[test]
+    fn test_clone_refcount() {
+        let obj = "hello".to_tcl_obj();
+        assert_eq!(unsafe { (*obj.as_ptr()).refCount }, 1);
+
+        let other = obj.clone();
+        assert_eq!(obj.as_ptr(), other.as_ptr());
+        assert_eq!(unsafe { (*obj.as_ptr()).refCount }, 2);
+
+        mem::drop(other);
+        assert_eq!(unsafe { (*obj.as_ptr()).refCount }, 1);
+    }
+
+    #[test]
+    fn test_objects_are_freed() {
+        let mut interp = TclInterp::new().unwrap();
+        let freed_before = FREED.load(Ordering::SeqCst);
+
+        for _ in 0..100 {
+            interp
+                .call(vec!["list".to_tcl_obj(), tracked_obj()])
+                .and_then(Completion::into_result)
+                .unwrap();
+        }
+
+        // The last list is still the interpreter's result, so replace it.
+        interp
+            .call(&["list"])
+            .and_then(Completion::into_result)
+            .unwrap();
+
+        assert_eq!(FREED.load(Ordering::SeqCst) - freed_before, 100);
+    }
 }
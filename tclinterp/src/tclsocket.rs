@@ -1,68 +1,633 @@
 use std::{
+    cell::RefCell,
     io::{self, Read, Write},
-    ptr,
+    mem,
+    net::{IpAddr, SocketAddr},
+    rc::{Rc, Weak},
 };
 
-use crate::{exceptions::TclError, tclinterp::TclInterp};
+use log::{debug, error};
+
+use crate::{
+    exceptions::{TclError, TclErrorKind},
+    tclinterp::{TclChannel, TclInterp, Translation, WeakTclInterp},
+};
 
 /// A wrapper around a Tcl socket that allows Read/Write trait usage.
-pub struct TclSocket {
-    interp: TclInterp,
-    id: String,
+///
+/// The socket is non-blocking and binary, so reading fails with `WouldBlock` when there's nothing
+/// to read yet. Use `on_readable` to be called when there is.
+///
+/// This type can be cloned to get another handle to the same socket, which is closed once the last
+/// one is dropped or `close` is called.
+#[derive(Clone)]
+pub struct TclSocket(Rc<SocketData>);
+
+struct SocketData {
+    /// The handlers the interpreter owns keep the socket alive, so this must not keep the
+    /// interpreter alive in turn.
+    interp: WeakTclInterp,
+    name: String,
+
+    /// Taken out when the socket is closed.
+    channel: RefCell<Option<TclChannel>>,
 }
 
-impl Drop for TclSocket {
+impl Drop for SocketData {
     fn drop(&mut self) {
-        self.interp.call(&["close", &self.id]).unwrap();
+        if let Err(err) = self.close() {
+            debug!("Closing socket {:?} failed: {}", self.name, err);
+        }
+    }
+}
+
+impl SocketData {
+    fn interp(&self) -> Result<TclInterp, TclError> {
+        self.interp.upgrade().ok_or_else(|| {
+            TclError::with_kind(
+                TclErrorKind::InterpDeleted,
+                "Tried to use interpreter after it was dropped",
+            )
+        })
+    }
+
+    fn close(&self) -> Result<(), TclError> {
+        let channel = match self.channel.borrow_mut().take() {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+        debug!("Closing socket {:?}", self.name);
+
+        // Deleting the interpreter closed the socket already, so we only need to let go of it.
+        let mut interp = match self.interp() {
+            Ok(interp) => interp,
+            Err(err) => {
+                mem::drop(channel);
+                return Err(err);
+            }
+        };
+
+        // This also removes the `fileevent` handlers, but not the commands behind them.
+        let res = interp.call(&["close", &self.name]).map(|_| ());
+        for event in &["readable", "writable"] {
+            let _ = interp.deletecommand(&handler_name(&self.name, event));
+        }
+
+        // The channel is only really closed once we let go of it too.
+        mem::drop(channel);
+        res
     }
 }
 
+/// Split an address given to `TclSocket::listen` into the host, without brackets, and the port.
+fn parse_addr(addr: &str) -> Result<(String, u16), TclError> {
+    let invalid = || TclError::new(format!("Invalid address {:?}", addr));
+
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+
+    // Otherwise it's a host name, or empty.
+    let i = addr.rfind(':').ok_or_else(invalid)?;
+    let (host, port) = (&addr[..i], &addr[i + 1..]);
+    if host.contains(&[':', '[', ']'][..]) {
+        return Err(invalid());
+    }
+    let port = port.parse::<u16>().map_err(|_| invalid())?;
+
+    Ok((host.to_owned(), port))
+}
+
+/// The name of the command that calls the Rust handler for `event` on the socket `name`.
+fn handler_name(name: &str, event: &str) -> String {
+    format!("tclsocket_{}_{}", name, event)
+}
+
 impl TclSocket {
     /// Connect to a specified host:port.
     pub fn connect(mut interp: TclInterp, host: &str, port: &str) -> Result<Self, TclError> {
-        let id = interp.call(&["socket", host, &port.to_string()])?;
-        let mut inst = Self { interp, id };
-        inst.fconfigure("blocking", "false")?;
-        inst.fconfigure("translation", "binary")?;
-        Ok(inst)
+        let name = interp.call(&["socket", host, port])?.into_result()?;
+        Self::from_channel(&interp, name)
     }
 
-    fn fconfigure(&mut self, key: &str, value: &str) -> Result<(), TclError> {
-        self.interp
-            .call(&["fconfigure", &self.id, &format!("-{}", key), value])
-            .map(|_| ())
+    /// Listen for connections on `addr`, given as `host:port`, and call `on_accept` with each new
+    /// connection and the address of its peer.
+    ///
+    /// IPv6 addresses have to be put in brackets, e.g. `[::1]:8080`. The host may be left empty to
+    /// listen on all interfaces, and port 0 picks a free port, which
+    /// `TclListener::local_addr` returns. Connections are only accepted while the event loop is
+    /// running. If `on_accept` fails or panics, the error is reported in the background, i.e. with
+    /// `bgerror`.
+    ///
+    /// The connection is closed once `on_accept` returns, unless it keeps the socket around, e.g.
+    /// by moving it into a handler.
+    ///
+    /// # Errors
+    /// This function fails if `addr` has no port or if Tcl can't listen on it.
+    pub fn listen<F>(
+        mut interp: TclInterp,
+        addr: &str,
+        on_accept: F,
+    ) -> Result<TclListener, TclError>
+    where
+        F: FnMut(&mut TclInterp, TclSocket, SocketAddr) -> Result<(), TclError> + 'static,
+    {
+        let (host, port) = parse_addr(addr)?;
+        let command = format!("tclsocket_accept_{}", rand::random::<u64>());
+
+        let on_accept = RefCell::new(on_accept);
+        interp.createcommand(&command, move |interp, args| {
+            // Tcl calls us with the new channel and the address of the peer.
+            let socket = TclSocket::from_channel(interp, args[0].to_string())?;
+            let ip = args[1]
+                .to_string()
+                .parse::<IpAddr>()
+                .map_err(|err| TclError::new(err.to_string()))?;
+            let port = args[2].extract::<i32>()? as u16;
+
+            let mut on_accept = on_accept
+                .try_borrow_mut()
+                .map_err(|_| TclError::new("Accept handler called itself recursively"))?;
+            on_accept(interp, socket, SocketAddr::new(ip, port)).map(|()| "")
+        })?;
+
+        let port = port.to_string();
+        let mut args = vec!["socket", "-server", command.as_str()];
+        if !host.is_empty() {
+            args.extend(&["-myaddr", host.as_str()]);
+        }
+        args.push(&port);
+
+        let name = match interp
+            .call(args)
+            .and_then(|completion| completion.into_result())
+        {
+            Ok(name) => name,
+            Err(err) => {
+                interp.deletecommand(&command)?;
+                return Err(err);
+            }
+        };
+        debug!("Listening on {:?} with socket {:?}", addr, name);
+
+        Ok(TclListener {
+            interp,
+            name,
+            command,
+        })
+    }
+
+    fn from_channel(interp: &TclInterp, name: String) -> Result<Self, TclError> {
+        let mut channel = TclChannel::new(interp.clone(), &name)?;
+        channel.set_blocking(false)?;
+        channel.set_translation(Translation::Binary)?;
+
+        Ok(TclSocket(Rc::new(SocketData {
+            interp: interp.downgrade(),
+            name,
+            channel: RefCell::new(Some(channel)),
+        })))
+    }
+
+    /// The name scripts know the socket by.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Close the socket, even if there are other handles to it, which fail from then on.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted or dropped, or a script already closed
+    /// the socket.
+    pub fn close(&mut self) -> Result<(), TclError> {
+        self.0.close()
+    }
+
+    fn set_handler<F>(&mut self, event: &str, callback: F) -> Result<(), TclError>
+    where
+        F: FnMut(&mut TclInterp, &mut TclSocket) -> Result<(), TclError> + 'static,
+    {
+        let command = handler_name(&self.0.name, event);
+        self.clear_handler(event)?;
+
+        // The handler must not keep the socket open.
+        let data: Weak<SocketData> = Rc::downgrade(&self.0);
+
+        let callback = RefCell::new(callback);
+        let recursive = format!("Socket {} handler called itself recursively", event);
+        let mut interp = self.0.interp()?;
+        interp.createcommand(&command, move |interp, _| {
+            let data = match data.upgrade() {
+                Some(data) => data,
+                None => return Ok(""),
+            };
+            let mut callback = callback
+                .try_borrow_mut()
+                .map_err(|_| TclError::new(recursive.clone()))?;
+            callback(interp, &mut TclSocket(data)).map(|()| "")
+        })?;
+        interp
+            .call(&["fileevent", &self.0.name, event, &command])?
+            .into_result()?;
+
+        Ok(())
+    }
+
+    fn clear_handler(&mut self, event: &str) -> Result<(), TclError> {
+        let mut interp = self.0.interp()?;
+        interp
+            .call(&["fileevent", &self.0.name, event, ""])?
+            .into_result()?;
+
+        // The command only exists if there was a handler.
+        let _ = interp.deletecommand(&handler_name(&self.0.name, event));
+        Ok(())
+    }
+
+    /// Call `callback` whenever there's something to read from the socket, including the end of
+    /// the connection, with `fileevent readable`.
+    ///
+    /// The callback only runs while the event loop is running. If it fails or panics, Tcl removes
+    /// the handler and reports the error in the background, i.e. with `bgerror`. Setting another
+    /// handler replaces this one.
+    ///
+    /// # Errors
+    /// This function fails if the socket was closed or its interpreter was dropped.
+    pub fn on_readable<F>(&mut self, callback: F) -> Result<(), TclError>
+    where
+        F: FnMut(&mut TclInterp, &mut TclSocket) -> Result<(), TclError> + 'static,
+    {
+        self.set_handler("readable", callback)
+    }
+
+    /// Call `callback` whenever the socket can be written to, with `fileevent writable`.
+    ///
+    /// This is the case most of the time, so the handler should usually be removed with
+    /// `clear_on_writable` once there's nothing left to write. Otherwise this works like
+    /// `on_readable`.
+    ///
+    /// # Errors
+    /// This function fails if the socket was closed or its interpreter was dropped.
+    pub fn on_writable<F>(&mut self, callback: F) -> Result<(), TclError>
+    where
+        F: FnMut(&mut TclInterp, &mut TclSocket) -> Result<(), TclError> + 'static,
+    {
+        self.set_handler("writable", callback)
+    }
+
+    /// Remove the handler set with `on_readable`, if any.
+    ///
+    /// # Errors
+    /// This function fails if the socket was closed or its interpreter was dropped.
+    pub fn clear_on_readable(&mut self) -> Result<(), TclError> {
+        self.clear_handler("readable")
+    }
+
+    /// Remove the handler set with `on_writable`, if any.
+    ///
+    /// # Errors
+    /// This function fails if the socket was closed or its interpreter was dropped.
+    pub fn clear_on_writable(&mut self) -> Result<(), TclError> {
+        self.clear_handler("writable")
+    }
+
+    fn with_channel<T>(&self, f: impl FnOnce(&mut TclChannel) -> io::Result<T>) -> io::Result<T> {
+        match self.0.channel.borrow_mut().as_mut() {
+            Some(channel) => f(channel),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Socket was closed",
+            )),
+        }
     }
 }
 
 impl Read for TclSocket {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let data = self
-            .interp
-            .call(&["read", &self.id, &buf.len().to_string()])?;
-        let data_bytes = data.as_bytes();
-
-        // Can we do this safely?
-        unsafe { ptr::copy_nonoverlapping(data_bytes.as_ptr(), buf.as_mut_ptr(), data_bytes.len()) }
-
-        Ok(data.len())
+        self.with_channel(|channel| channel.read(buf))
     }
 }
 
 impl Write for TclSocket {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let buf_str =
-            std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.with_channel(|channel| channel.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_channel(|channel| channel.flush())
+    }
+}
+
+/// A server socket created with `TclSocket::listen`, which stops listening when dropped.
+///
+/// Connections that were already accepted stay open.
+pub struct TclListener {
+    interp: TclInterp,
+    name: String,
+    command: String,
+}
 
-        self.interp
-            .call(&["puts", "-nonewline", &self.id, buf_str])
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+impl Drop for TclListener {
+    fn drop(&mut self) {
+        debug!("Closing server socket {:?}", self.name);
 
-        Ok(buf.len())
+        if let Err(err) = self.interp.call(&["close", &self.name]) {
+            error!("Closing server socket {:?} failed: {}", self.name, err);
+        }
+        let _ = self.interp.deletecommand(&self.command);
     }
+}
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.interp.call(&["flush", &self.id])?;
+impl TclListener {
+    /// The name scripts know the server socket by.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-        Ok(())
+    /// The address the server is listening on.
+    ///
+    /// If it listens on several, e.g. on both IPv4 and IPv6 when no host was given, this is the
+    /// first one.
+    ///
+    /// # Errors
+    /// This function fails if the interpreter was deleted.
+    pub fn local_addr(&mut self) -> Result<SocketAddr, TclError> {
+        // This is a list of the address, the host name and the port for each address.
+        let sockname = self
+            .interp
+            .call_obj(&["fconfigure", &self.name, "-sockname"])?
+            .into_result()?
+            .extract::<Vec<String>>()?;
+
+        match &sockname[..] {
+            [ip, _, port, ..] => {
+                let ip = ip
+                    .parse::<IpAddr>()
+                    .map_err(|err| TclError::new(err.to_string()))?;
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|err| TclError::new(err.to_string()))?;
+                Ok(SocketAddr::new(ip, port))
+            }
+            _ => Err(TclError::new(format!(
+                "Unexpected socket name {:?}",
+                sockname
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        cell::Cell,
+        net::{Shutdown, TcpListener, TcpStream},
+        time::{Duration, Instant},
+    };
+
+    use crate::tclinterp::test_utils::{catch_bgerrors, getvar_string};
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    /// Read from a blocking stream until the other side closes it.
+    fn read_all(mut stream: TcpStream) -> Vec<u8> {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_echo_server() {
+        let mut interp = TclInterp::new().unwrap();
+        let peers = Rc::new(RefCell::new(Vec::new()));
+
+        let mut listener = {
+            let peers = peers.clone();
+            TclSocket::listen(interp.clone(), "127.0.0.1:0", move |_, mut socket, peer| {
+                peers.borrow_mut().push(peer);
+
+                // The handler keeps the socket open until it closes it.
+                let mut keep = socket.clone();
+                socket.on_readable(move |interp, socket| {
+                    let mut buf = [0; 64];
+                    match socket.read(&mut buf) {
+                        Ok(0) => {
+                            keep.close()?;
+                            interp.quit();
+                        }
+                        Ok(n) => socket.write_all(&buf[..n])?,
+                        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(err) => return Err(err.into()),
+                    }
+                    Ok(())
+                })
+            })
+            .unwrap()
+        };
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(addr.ip(), "127.0.0.1".parse::<IpAddr>().unwrap());
+        assert_ne!(addr.port(), 0);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"hello").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        assert!(interp.run_until(deadline()).unwrap());
+        assert_eq!(read_all(client), b"hello");
+        assert_eq!(peers.borrow().len(), 1);
+        assert_eq!(peers.borrow()[0].ip(), addr.ip());
+    }
+
+    #[test]
+    fn test_dropped_in_accept() {
+        let mut interp = TclInterp::new().unwrap();
+        let accepted = Rc::new(Cell::new(false));
+
+        let mut listener = {
+            let accepted = accepted.clone();
+            TclSocket::listen(interp.clone(), ":0", move |interp, _, _| {
+                accepted.set(true);
+                interp.quit();
+                Ok(())
+            })
+            .unwrap()
+        };
+        let port = listener.local_addr().unwrap().port();
+
+        let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        assert!(interp.run_until(deadline()).unwrap());
+        assert!(accepted.get());
+        assert!(read_all(client).is_empty());
+
+        mem::drop(listener);
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn test_client() {
+        let mut interp = TclInterp::new().unwrap();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port().to_string();
+
+        let mut socket = TclSocket::connect(interp.clone(), "127.0.0.1", &port).unwrap();
+        let (mut peer, _) = server.accept().unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(
+            socket.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        socket
+            .on_writable(|_, socket| {
+                socket.write_all(b"ping")?;
+                socket.clear_on_writable()
+            })
+            .unwrap();
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        {
+            let received = received.clone();
+            socket
+                .on_readable(move |interp, socket| {
+                    let mut buf = [0; 16];
+                    let n = socket.read(&mut buf)?;
+                    received.borrow_mut().extend_from_slice(&buf[..n]);
+
+                    interp.quit();
+                    Ok(())
+                })
+                .unwrap();
+        }
+
+        interp.update().unwrap();
+        let mut buf = [0; 4];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        peer.write_all(b"pong").unwrap();
+        assert!(interp.run_until(deadline()).unwrap());
+        assert_eq!(&received.borrow()[..], b"pong");
+
+        // Closing removes the handlers, and with them everything they captured.
+        socket.close().unwrap();
+        assert_eq!(Rc::strong_count(&received), 1);
+        assert_eq!(
+            socket.write(b"more").unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+        assert!(read_all(peer).is_empty());
+    }
+
+    #[test]
+    fn test_handler_errors() {
+        let mut interp = TclInterp::new().unwrap();
+        catch_bgerrors(&mut interp);
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port().to_string();
+        let mut socket = TclSocket::connect(interp.clone(), "127.0.0.1", &port).unwrap();
+
+        socket
+            .on_writable(|_, _| Err(TclError::new("failed")))
+            .unwrap();
+        interp.eval("vwait errors".to_owned()).unwrap();
+
+        assert_eq!(getvar_string(&interp, "errors"), "failed");
+        assert_eq!(
+            interp
+                .call(&["fileevent", socket.name(), "writable"])
+                .unwrap()
+                .into_result()
+                .unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_interp_dropped() {
+        let interp = TclInterp::new().unwrap();
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port().to_string();
+
+        let mut socket = TclSocket::connect(interp.clone(), "127.0.0.1", &port).unwrap();
+        let (peer, _) = server.accept().unwrap();
+
+        // The handler keeping the socket alive mustn't keep the interpreter alive in turn.
+        let data = Rc::new(());
+        {
+            let data = data.clone();
+            let keep = socket.clone();
+            socket
+                .on_readable(move |_, _| {
+                    let _ = (&data, &keep);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        mem::drop(interp);
+        assert_eq!(Rc::strong_count(&data), 1);
+
+        let err = socket.clear_on_readable().unwrap_err();
+        assert_eq!(err.kind(), TclErrorKind::InterpDeleted);
+
+        // Our handle still kept the connection open.
+        let err = socket.close().unwrap_err();
+        assert_eq!(err.kind(), TclErrorKind::InterpDeleted);
+        assert!(read_all(peer).is_empty());
+    }
+
+    #[test]
+    fn test_listen_ipv6() {
+        let interp = TclInterp::new().unwrap();
+        let mut listener = TclSocket::listen(interp, "[::1]:0", |_, _, _| Ok(())).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(addr.ip(), "::1".parse::<IpAddr>().unwrap());
+
+        TcpStream::connect(addr).unwrap();
+    }
+
+    #[test]
+    fn test_parse_addr() {
+        assert_eq!(parse_addr("[::1]:80").unwrap(), ("::1".to_owned(), 80));
+        assert_eq!(
+            parse_addr("127.0.0.1:80").unwrap(),
+            ("127.0.0.1".to_owned(), 80)
+        );
+        assert_eq!(
+            parse_addr("localhost:80").unwrap(),
+            ("localhost".to_owned(), 80)
+        );
+        assert_eq!(parse_addr(":0").unwrap(), ("".to_owned(), 0));
+
+        for addr in &[
+            "127.0.0.1",
+            "::1",
+            "::1:80",
+            "[::1]",
+            "localhost:http",
+            ":70000",
+        ] {
+            assert!(parse_addr(addr).is_err(), "{:?} was accepted", addr);
+        }
+    }
+
+    #[test]
+    fn test_listen_errors() {
+        let interp = TclInterp::new().unwrap();
+        assert!(TclSocket::listen(interp.clone(), "127.0.0.1", |_, _, _| Ok(())).is_err());
+
+        // The port is taken.
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        assert!(TclSocket::listen(interp, &addr, |_, _, _| Ok(())).is_err());
     }
 }
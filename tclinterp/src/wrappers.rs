@@ -1,6 +1,13 @@
-use std::os::raw::*;
+use std::{
+    any::Any,
+    os::raw::*,
+    panic::{self, AssertUnwindSafe},
+};
 
-use crate::tclobj::{TclObj, ToTclObj};
+use crate::{
+    exceptions::TclError,
+    tclobj::{TclObj, ToTclObj},
+};
 
 /// A wrapper around a list of Tcl objects.
 #[derive(Debug)]
@@ -33,3 +40,51 @@ impl Objv {
         self.1.as_ptr()
     }
 }
+
+/// Run a Rust callback that was called by Tcl, turning a panic into an error.
+///
+/// Unwinding into Tcl's C code is undefined behavior, so every `extern "C"` function we hand to
+/// Tcl must run its body through this. `what` describes the callback in the error message.
+pub fn catch_panic<T>(what: &str, f: impl FnOnce() -> Result<T, TclError>) -> Result<T, TclError> {
+    catch_panic_with(|| what.to_owned(), f)
+}
+
+/// Like `catch_panic`, but only describes the callback if it panicked.
+pub fn catch_panic_with<T>(
+    what: impl FnOnce() -> String,
+    f: impl FnOnce() -> Result<T, TclError>,
+) -> Result<T, TclError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        Err(TclError::new(format!(
+            "{} panicked: {}",
+            what(),
+            panic_message(&payload)
+        )))
+    })
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<Any>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic("test", || Ok(1)).unwrap(), 1);
+
+        let err = catch_panic("test", || -> Result<(), TclError> { panic!("oh no") }).unwrap_err();
+        assert_eq!(err.message(), "test panicked: oh no");
+
+        let err = catch_panic("test", || -> Result<(), TclError> { panic!("{}", 42) }).unwrap_err();
+        assert_eq!(err.message(), "test panicked: 42");
+    }
+}
@@ -2,7 +2,9 @@ use std::sync::Once;
 
 use pyo3::{create_exception, prelude::*, types::*, wrap_pyfunction};
 
-use tclinterp::{TclInterp, ToTclObj};
+#[cfg(unix)]
+use tclinterp::FileMask;
+use tclinterp::{Completion, EventFlags, TclInterp, ToTclObj};
 
 #[pyclass]
 pub struct TkApp {
@@ -16,11 +18,11 @@ impl TkApp {
         LOGGER_INIT.call_once(env_logger::init);
 
         let mut inst = Self {
-            interp: TclInterp::new().map_err(|err| TclError::py_err(err.0))?,
+            interp: TclInterp::new().map_err(|err| TclError::py_err(err.to_string()))?,
         };
         inst.interp
             .init_tk()
-            .map_err(|err| TclError::py_err(err.0))?;
+            .map_err(|err| TclError::py_err(err.to_string()))?;
         Ok(inst)
     }
 }
@@ -31,68 +33,111 @@ impl TkApp {
     fn call(&mut self, args: &PyTuple) -> PyResult<String> {
         self.interp
             .call(args)
-            .map_err(|err| TclError::py_err(err.0))
+            .and_then(Completion::into_result)
+            .map_err(|err| TclError::py_err(err.to_string()))
     }
 
     fn eval(&mut self, code: String) -> PyResult<String> {
         self.interp
             .eval(code)
-            .map_err(|err| TclError::py_err(err.0))
+            .and_then(Completion::into_result)
+            .map_err(|err| TclError::py_err(err.to_string()))
     }
 
     fn splitlist(&mut self, arg: &PyString) -> PyResult<Vec<String>> {
         self.interp
             .splitlist(arg)
-            .map_err(|err| TclError::py_err(err.0))
+            .map_err(|err| TclError::py_err(err.to_string()))
     }
 
     fn getboolean(&mut self, arg: &PyString) -> PyResult<bool> {
         self.interp
             .getboolean(arg.to_string()?.to_string())
-            .map_err(|err| TclError::py_err(err.0))
+            .map_err(|err| TclError::py_err(err.to_string()))
     }
 
     fn delete(&mut self) -> PyResult<()> {
-        self.interp.delete().map_err(|err| TclError::py_err(err.0))
+        self.interp.delete().map_err(|err| TclError::py_err(err.to_string()))
     }
 
     fn createcommand(&mut self, name: &str, func: Py<PyAny>) -> PyResult<()> {
         // TODO: Better errors here.
         self.interp
-            .createcommand(name, Box::new(func), |cmd_data, args| {
+            .createcommand(name, move |_, args| {
                 let gil = Python::acquire_gil();
                 let py = gil.python();
 
-                let func = cmd_data
-                    .data
-                    .downcast_ref::<Py<PyAny>>()
-                    .unwrap()
-                    .to_owned();
-
-                let args = args
-                    .iter()
-                    .map(|s| s.to_str())
-                    .collect::<Result<Vec<_>, _>>()
-                    .unwrap();
+                let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
 
                 func.to_object(py)
                     .call(py, PyTuple::new(py, args), None)
                     .map(|v| v.as_ref(py).to_tcl_obj())
-                    .map_err(|e| crate::errmsg(py, &e).to_tcl_obj())
+                    .map_err(|e| tclinterp::TclError::new(crate::errmsg(py, &e)))
             })
-            .map_err(|err| TclError::py_err(err.0))
+            .map_err(|err| TclError::py_err(err.to_string()))
     }
 
     fn deletecommand(&mut self, name: &str) -> PyResult<()> {
         self.interp
             .deletecommand(name)
-            .map_err(|err| TclError::py_err(err.0))
+            .map_err(|err| TclError::py_err(err.to_string()))
     }
 
     fn mainloop(&mut self, _arg: &PyAny) -> PyResult<()> {
         self.interp
             .mainloop()
-            .map_err(|err| TclError::py_err(err.0))
+            .map_err(|err| TclError::py_err(err.to_string()))
+    }
+
+    fn quit(&mut self) -> PyResult<()> {
+        self.interp.quit();
+        Ok(())
+    }
+
+    #[args(flags = "0")]
+    fn dooneevent(&mut self, flags: i32) -> PyResult<i32> {
+        self.interp
+            .do_one_event(EventFlags::from_bits(flags))
+            .map(|processed| processed as i32)
+            .map_err(|err| TclError::py_err(err.to_string()))
+    }
+}
+
+// Tcl only has file handlers on Unix.
+#[cfg(unix)]
+#[pymethods]
+impl TkApp {
+    fn createfilehandler(&mut self, file: &PyAny, mask: i32, func: Py<PyAny>) -> PyResult<()> {
+        let fd = fileno(file)?;
+        let file = file.to_object(file.py());
+
+        self.interp
+            .create_file_handler(fd, FileMask::from_bits(mask), move |_, mask| {
+                let gil = Python::acquire_gil();
+                let py = gil.python();
+
+                let args = vec![file.clone_ref(py), mask.bits().to_object(py)];
+
+                func.to_object(py)
+                    .call(py, PyTuple::new(py, args), None)
+                    .map(|_| ())
+                    .map_err(|e| tclinterp::TclError::new(crate::errmsg(py, &e)))
+            })
+            .map_err(|err| TclError::py_err(err.to_string()))
+    }
+
+    fn deletefilehandler(&mut self, file: &PyAny) -> PyResult<()> {
+        self.interp.delete_file_handler(fileno(file)?);
+        Ok(())
+    }
+}
+
+/// Get the file descriptor of `file`, which is either one already or has a `fileno()` method.
+#[cfg(unix)]
+fn fileno(file: &PyAny) -> PyResult<i32> {
+    match file.extract::<i32>() {
+        Ok(fd) => Ok(fd),
+        Err(_) => file.call_method0("fileno")?.extract::<i32>(),
     }
 }
 
@@ -127,6 +172,12 @@ fn create() -> PyResult<Py<TkApp>> {
 fn mystcl(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_wrapped(wrap_pyfunction!(create))?;
     m.add("TclError", py.get_type::<TclError>())?;
+    #[cfg(unix)]
+    {
+        m.add("READABLE", FileMask::READABLE.bits())?;
+        m.add("WRITABLE", FileMask::WRITABLE.bits())?;
+        m.add("EXCEPTION", FileMask::EXCEPTION.bits())?;
+    }
     Ok(())
 }
 